@@ -1,15 +1,30 @@
 use eframe::egui::{self, ScrollArea};
 use std::time::Instant;
 
-use vlife_simulator::{CellId, Scalar, Simulator, Vec2};
+use vlife_simulator::{CellId, PruningMutation, Scalar, Simulator, Vec2};
 
+use crate::camera::Camera;
 use crate::central_panel::CentralPanel;
+use crate::charts_panel::ChartsPanel;
+use crate::genome_panel::{GenomeEditor, GenomePanel};
+use crate::network_panel::NetworkPanel;
 use crate::top_bar::TopBar;
+use crate::world_panel::Tool;
 
 const NUM_INITIAL_CELLS: usize = 500;
 
+/// Lets the networks of the offspring get rid of the cost of the neurons they don't need.
+const PRUNING_MUTATION: PruningMutation = PruningMutation {
+    connection: 0.05,
+    neuron: 0.01,
+    input: 0.01,
+    restore: 0.02,
+};
+
 const DEFAULT_DELTA: Scalar = 1.0 / 60.0; // 60 Hz
 
+const DEFAULT_CHARTS_WINDOW: Scalar = 120.0;
+
 pub(crate) struct Application {
     last_update: Option<Instant>,
     frame_time: f64,
@@ -21,6 +36,18 @@ pub(crate) struct Application {
     pub(crate) world_size: Vec2,
     pub(crate) simulator: Simulator,
     pub(crate) selected_cell: Option<CellId>,
+    /// All the selected cells, including the `selected_cell`.
+    pub(crate) selected_cells: Vec<CellId>,
+    pub(crate) tool: Tool,
+    /// Cell moved by the move tool.
+    pub(crate) dragged_cell: Option<CellId>,
+    /// Corners of the box of the selection tool, in world coordinates.
+    pub(crate) selection_box: Option<(Vec2, Vec2)>,
+    pub(crate) camera: Camera,
+    pub(crate) show_charts: bool,
+    pub(crate) genome_editor: GenomeEditor,
+    /// Simulated seconds shown by the charts.
+    pub(crate) charts_window: Scalar,
     pub(crate) paused: bool,
     pub(crate) speed: f32,
 }
@@ -39,6 +66,14 @@ impl Application {
             world_size,
             simulator,
             selected_cell: Some(0),
+            selected_cells: vec![0],
+            tool: Tool::Pan,
+            dragged_cell: None,
+            selection_box: None,
+            camera: Camera::new(world_size),
+            show_charts: false,
+            genome_editor: GenomeEditor::default(),
+            charts_window: DEFAULT_CHARTS_WINDOW,
             paused: false,
             speed: 1.0,
         }
@@ -46,27 +81,38 @@ impl Application {
 
     fn ui(&mut self, ctx: &egui::Context) {
         TopBar::ui(ctx, self);
+        ChartsPanel::ui(ctx, self);
 
         egui::TopBottomPanel::bottom("bottom")
             .min_height(300.0)
             .show(ctx, |ui| {
-                let scroll_area = ScrollArea::vertical().auto_shrink([false; 2]);
-                scroll_area.show(ui, |ui| {
-                    if let Some(cell_id) = self.selected_cell {
-                        if let Some(cell) = self.simulator.get_cell_view(cell_id) {
+                let cell = self
+                    .selected_cell
+                    .and_then(|cell_id| self.simulator.get_cell_view(cell_id));
+                ui.columns(2, |columns| {
+                    if let Some(cell) = &cell {
+                        NetworkPanel::ui(&mut columns[0], &cell.network_graph());
+                    }
+                    let scroll_area = ScrollArea::vertical().auto_shrink([false; 2]);
+                    scroll_area.show(&mut columns[1], |ui| {
+                        if let Some(cell) = &cell {
                             ui.monospace(format!("{cell}"));
                         }
-                    }
+                    });
                 });
             });
 
+        GenomePanel::ui(ctx, self);
+
         let dt = self.update_simulation();
 
         CentralPanel::ui(ctx, self, dt);
     }
 
     fn create_simulator(world_size: Vec2) -> Simulator {
-        Simulator::new(world_size).with_min_cells(NUM_INITIAL_CELLS)
+        Simulator::new(world_size)
+            .with_min_cells(NUM_INITIAL_CELLS)
+            .with_pruning_mutation(PRUNING_MUTATION)
     }
 
     fn update_simulation(&mut self) -> Scalar {
@@ -114,7 +160,38 @@ impl Application {
     }
 
     pub(crate) fn on_cell_selected(&mut self, cell_id: CellId) {
-        self.selected_cell = Some(cell_id)
+        self.selected_cell = Some(cell_id);
+        self.selected_cells = vec![cell_id];
+    }
+
+    pub(crate) fn on_cells_selected(&mut self, cell_ids: Vec<CellId>) {
+        self.selected_cell = cell_ids.first().copied();
+        self.selected_cells = cell_ids;
+    }
+
+    /// Adds a copy of every selected cell next to it, and selects the copies.
+    pub(crate) fn on_clone_button(&mut self) {
+        let mut clones = Vec::new();
+        for cell_id in self.selected_cells.iter().copied() {
+            let Some((genome, position)) = self.simulator.get_cell_view(cell_id).map(|cell| {
+                let position = cell.position() + Vec2::new(2.0 * cell.radius(), 0.0);
+                (cell.genome(), position)
+            }) else {
+                continue;
+            };
+            clones.push(
+                self.simulator
+                    .add_cell_with_genome(&genome, position, Some(cell_id)),
+            );
+        }
+        self.on_cells_selected(clones);
+    }
+
+    pub(crate) fn on_delete_button(&mut self) {
+        for cell_id in std::mem::take(&mut self.selected_cells) {
+            self.simulator.remove_cell(cell_id);
+        }
+        self.selected_cell = None;
     }
 
     pub(crate) fn on_pause_play_button(&mut self) {
@@ -0,0 +1,86 @@
+use eframe::{
+    egui::{self, Pos2, Rect},
+    emath::RectTransform,
+};
+
+use vlife_simulator::Vec2;
+
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 50.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CameraMode {
+    /// Moved and zoomed by the user.
+    Free,
+    /// The whole world fits in the panel.
+    Fit,
+    /// Centered on the selected cell.
+    Follow,
+}
+
+/// View of the world in the panel.
+pub(crate) struct Camera {
+    pub(crate) mode: CameraMode,
+    /// World position in the center of the panel.
+    center: Pos2,
+    /// Screen points per world unit.
+    zoom: f32,
+}
+
+impl Camera {
+    pub(crate) fn new(world_size: Vec2) -> Self {
+        Self {
+            mode: CameraMode::Fit,
+            center: Pos2::new(0.5 * world_size.x as f32, 0.5 * world_size.y as f32),
+            zoom: 1.0,
+        }
+    }
+
+    pub(crate) fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Updates the view for the mode, where `target` is the position of the selected cell.
+    pub(crate) fn update(&mut self, screen_rect: Rect, world_size: Vec2, target: Option<Vec2>) {
+        match self.mode {
+            CameraMode::Free => {}
+            CameraMode::Fit => {
+                let world_size = egui::vec2(world_size.x as f32, world_size.y as f32);
+                self.center = (0.5 * world_size).to_pos2();
+                self.zoom = (screen_rect.size() / world_size).min_elem();
+            }
+            CameraMode::Follow => {
+                if let Some(target) = target {
+                    self.center = Pos2::new(target.x as f32, target.y as f32);
+                }
+            }
+        }
+    }
+
+    /// Transformation from world positions to screen positions.
+    pub(crate) fn to_screen(&self, screen_rect: Rect) -> RectTransform {
+        let world_rect = Rect::from_center_size(self.center, screen_rect.size() / self.zoom);
+        RectTransform::from_to(world_rect, screen_rect)
+    }
+
+    /// Moves the view by a screen offset.
+    pub(crate) fn pan(&mut self, screen_delta: egui::Vec2) {
+        self.mode = CameraMode::Free;
+        self.center -= screen_delta / self.zoom;
+    }
+
+    /// Zooms by the factor, keeping the same world position under the screen position.
+    pub(crate) fn zoom_at(&mut self, screen_rect: Rect, screen_pos: Pos2, factor: f32) {
+        if self.mode == CameraMode::Fit {
+            self.mode = CameraMode::Free;
+        }
+        let world_pos = self
+            .to_screen(screen_rect)
+            .inverse()
+            .transform_pos(screen_pos);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        if self.mode == CameraMode::Free {
+            self.center = world_pos - (screen_pos - screen_rect.center()) / self.zoom;
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use eframe::egui::{
+    self,
+    plot::{Legend, Line, Plot, PlotPoints},
+    ScrollArea,
+};
+
+use vlife_simulator::{Distribution, Sample, Scalar};
+
+use crate::app::Application;
+
+const PLOT_HEIGHT: f32 = 120.0;
+
+/// Samples in the window of the charts.
+type Samples<'a> = std::collections::vec_deque::Iter<'a, Sample>;
+
+pub struct ChartsPanel;
+
+impl ChartsPanel {
+    /// Charts of the population over the last simulated seconds.
+    /// The samples are taken by the simulator, so nothing is sampled while it is paused.
+    pub(crate) fn ui(ctx: &egui::Context, app: &mut Application) {
+        if !app.show_charts {
+            return;
+        }
+        egui::SidePanel::right("charts")
+            .resizable(true)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut app.charts_window, 10.0..=600.0)
+                        .logarithmic(true)
+                        .fixed_decimals(0)
+                        .text("Window (s)"),
+                );
+                ui.separator();
+
+                let samples = app.simulator.statistics().samples();
+                let start_time =
+                    samples.back().map_or(0.0, |sample| sample.time) - app.charts_window;
+                let samples =
+                    samples.range(samples.partition_point(|sample| sample.time < start_time)..);
+
+                ScrollArea::vertical().show(ui, |ui| {
+                    ui.label("Population");
+                    Self::plot(ui, "population", |plot| {
+                        Self::line(plot, samples.clone(), "cells", |sample| {
+                            sample.population as Scalar
+                        });
+                    });
+
+                    ui.label("Births and deaths per second");
+                    Self::plot(ui, "births_deaths", |plot| {
+                        Self::rate(plot, samples.clone(), "births", |sample| sample.births);
+                        Self::rate(plot, samples.clone(), "deaths", |sample| sample.deaths);
+                        Self::rate(plot, samples.clone(), "reseeds", |sample| sample.reseeds);
+                    });
+
+                    ui.label("Energy");
+                    Self::plot(ui, "energy", |plot| {
+                        Self::line(plot, samples.clone(), "mean", |sample| sample.energy.mean);
+                    });
+
+                    ui.label("Fitness of the rank");
+                    Self::plot(ui, "fitness", |plot| {
+                        Self::line(plot, samples.clone(), "mean", |sample| sample.fitness.mean);
+                        Self::line(plot, samples.clone(), "max", |sample| sample.fitness.max);
+                    });
+
+                    ui.label("Size");
+                    Self::plot(ui, "size", |plot| {
+                        Self::distribution(plot, samples.clone(), |sample| &sample.size);
+                    });
+
+                    ui.label("Movement speed limit");
+                    Self::plot(ui, "movement_speed_limit", |plot| {
+                        Self::distribution(plot, samples.clone(), |sample| {
+                            &sample.movement_speed_limit
+                        });
+                    });
+
+                    ui.label("Age");
+                    Self::plot(ui, "age", |plot| {
+                        Self::distribution(plot, samples.clone(), |sample| &sample.age);
+                    });
+                });
+            });
+    }
+
+    fn plot(ui: &mut egui::Ui, id: &str, add_lines: impl FnOnce(&mut egui::plot::PlotUi)) {
+        Plot::new(id)
+            .height(PLOT_HEIGHT)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false)
+            .link_axis("charts", true, false)
+            .legend(Legend::default())
+            .show(ui, add_lines);
+    }
+
+    fn line(
+        plot: &mut egui::plot::PlotUi,
+        samples: Samples,
+        name: &str,
+        value: impl Fn(&Sample) -> Scalar,
+    ) {
+        let points: Vec<_> = samples.map(|sample| [sample.time, value(sample)]).collect();
+        plot.line(Line::new(PlotPoints::new(points)).name(name));
+    }
+
+    /// Number of events per second between consecutive samples.
+    fn rate(
+        plot: &mut egui::plot::PlotUi,
+        samples: Samples,
+        name: &str,
+        count: impl Fn(&Sample) -> usize,
+    ) {
+        let points: Vec<_> = samples
+            .clone()
+            .zip(samples.skip(1))
+            .filter_map(|(previous, sample)| {
+                let duration = sample.time - previous.time;
+                (duration > 0.0).then(|| [sample.time, count(sample) as Scalar / duration])
+            })
+            .collect();
+        plot.line(Line::new(PlotPoints::new(points)).name(name));
+    }
+
+    /// Median of a population variable, between the 10th and 90th percentiles.
+    fn distribution(
+        plot: &mut egui::plot::PlotUi,
+        samples: Samples,
+        distribution: impl Fn(&Sample) -> &Distribution,
+    ) {
+        Self::line(plot, samples.clone(), "p10", |sample| {
+            distribution(sample).p10
+        });
+        Self::line(plot, samples.clone(), "median", |sample| {
+            distribution(sample).median
+        });
+        Self::line(plot, samples, "p90", |sample| distribution(sample).p90);
+    }
+}
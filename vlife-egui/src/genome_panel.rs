@@ -0,0 +1,153 @@
+use eframe::egui::{self, CollapsingHeader, DragValue, ScrollArea};
+
+use vlife_simulator::{CellId, Genome, Vec2};
+
+use crate::app::Application;
+
+/// Maximum number of genes listed when searching.
+const MAX_SEARCH_RESULTS: usize = 200;
+
+/// Genome of the selected cell, which can be edited and then applied to the cell or to a clone.
+#[derive(Default)]
+pub(crate) struct GenomeEditor {
+    pub(crate) visible: bool,
+    cell_id: Option<CellId>,
+    genome: Option<Genome>,
+    search: String,
+}
+
+pub struct GenomePanel;
+
+impl GenomePanel {
+    pub(crate) fn ui(ctx: &egui::Context, app: &mut Application) {
+        let mut visible = app.genome_editor.visible;
+        egui::Window::new("Genome")
+            .open(&mut visible)
+            .default_width(360.0)
+            .default_height(480.0)
+            .show(ctx, |ui| {
+                if app.genome_editor.cell_id != app.selected_cell {
+                    Self::reload(app);
+                }
+                if app.genome_editor.genome.is_none() {
+                    ui.label("No cell selected");
+                    return;
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Reload").clicked() {
+                        Self::reload(app);
+                    }
+                    if ui.button("Apply to cell").clicked() {
+                        Self::apply(app);
+                    }
+                    if ui.button("Spawn clone").clicked() {
+                        Self::spawn_clone(app);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Search");
+                    ui.text_edit_singleline(&mut app.genome_editor.search);
+                });
+                ui.separator();
+
+                let editor = &mut app.genome_editor;
+                if let Some(genome) = &mut editor.genome {
+                    ScrollArea::vertical()
+                        .auto_shrink([false; 2])
+                        .show(ui, |ui| {
+                            if editor.search.is_empty() {
+                                Self::tree(ui, genome, None);
+                            } else {
+                                Self::search_results(ui, genome, &editor.search);
+                            }
+                        });
+                }
+            });
+        app.genome_editor.visible = visible;
+    }
+
+    /// Genes under the path, with a collapsible header for every nested path.
+    fn tree(ui: &mut egui::Ui, genome: &mut Genome, path: Option<&str>) {
+        for child in genome.children(path) {
+            let key = match path {
+                Some(path) => format!("{path}/{child}"),
+                None => child.clone(),
+            };
+            if genome.get(None, &key).is_some() {
+                Self::gen(ui, genome, &key, &child);
+            } else {
+                CollapsingHeader::new(&child)
+                    .id_source(&key)
+                    .show(ui, |ui| Self::tree(ui, genome, Some(&key)));
+            }
+        }
+    }
+
+    /// Flat list of the genes whose path contains the searched text.
+    fn search_results(ui: &mut egui::Ui, genome: &mut Genome, search: &str) {
+        let keys: Vec<String> = genome
+            .genes()
+            .map(|(key, _)| key)
+            .filter(|key| key.contains(search))
+            .take(MAX_SEARCH_RESULTS)
+            .map(str::to_string)
+            .collect();
+        for key in keys.iter() {
+            Self::gen(ui, genome, key, key);
+        }
+        if keys.len() == MAX_SEARCH_RESULTS {
+            ui.label("...");
+        }
+    }
+
+    fn gen(ui: &mut egui::Ui, genome: &mut Genome, key: &str, label: &str) {
+        if let Some(mut value) = genome.get(None, key).map(|gen| gen.value()) {
+            ui.horizontal(|ui| {
+                if ui
+                    .add(DragValue::new(&mut value).speed(0.01).max_decimals(4))
+                    .changed()
+                {
+                    genome.set(key, value);
+                }
+                ui.label(label);
+            });
+        }
+    }
+
+    fn reload(app: &mut Application) {
+        let editor = &mut app.genome_editor;
+        editor.cell_id = app.selected_cell;
+        editor.genome = app
+            .selected_cell
+            .and_then(|cell_id| app.simulator.get_cell_view(cell_id))
+            .map(|cell| cell.genome());
+    }
+
+    fn apply(app: &mut Application) {
+        if let (Some(cell_id), Some(genome)) =
+            (app.genome_editor.cell_id, &app.genome_editor.genome)
+        {
+            app.simulator.set_cell_genome(cell_id, genome);
+        }
+    }
+
+    /// Adds a cell with the edited genome next to the selected one, and selects it.
+    fn spawn_clone(app: &mut Application) {
+        let editor = &app.genome_editor;
+        let Some((cell_id, genome)) = editor.cell_id.zip(editor.genome.as_ref()) else {
+            return;
+        };
+        let Some(position) = app
+            .simulator
+            .get_cell_view(cell_id)
+            .map(|cell| cell.position() + Vec2::new(2.0 * cell.radius(), 0.0))
+        else {
+            return;
+        };
+        let clone_id = app
+            .simulator
+            .add_cell_with_genome(genome, position, Some(cell_id));
+        app.on_cell_selected(clone_id);
+    }
+}
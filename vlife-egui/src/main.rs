@@ -1,5 +1,9 @@
 mod app;
+mod camera;
 mod central_panel;
+mod charts_panel;
+mod genome_panel;
+mod network_panel;
 mod top_bar;
 mod world_panel;
 
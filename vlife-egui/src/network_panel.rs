@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use eframe::egui::{self, Color32, Pos2, Rect, Rgba, Sense, Stroke};
+
+use vlife_simulator::{GraphNodeKind, NetworkGraph, Scalar};
+
+const NODE_RADIUS: f32 = 4.0;
+const MAX_EDGE_WIDTH: f32 = 3.0;
+
+pub struct NetworkPanel;
+
+impl NetworkPanel {
+    /// Draws the network of the cell in layers, from the inputs on the left to the outputs on the right.
+    /// The nodes are colored by their value and the edges by their weight,
+    /// blue when positive and red when negative.
+    pub(crate) fn ui(ui: &mut egui::Ui, graph: &NetworkGraph) {
+        let (response, painter) = ui.allocate_painter(
+            egui::Vec2::new(ui.available_width(), ui.available_height()),
+            Sense::hover(),
+        );
+        let rect = response.rect.shrink(2.0 * NODE_RADIUS);
+        let positions = Self::layout(graph, rect);
+
+        for edge in graph.edges.iter().filter(|edge| edge.enabled) {
+            if let (Some(from), Some(to)) = (positions.get(&edge.from), positions.get(&edge.to)) {
+                let width = (edge.weight.abs() as f32).min(MAX_EDGE_WIDTH);
+                let color = Self::color(edge.weight).gamma_multiply(0.6);
+                painter.line_segment([*from, *to], Stroke::new(width, color));
+            }
+        }
+
+        let hover_pos = response.hover_pos();
+        let mut hovered = None;
+        for node in graph.nodes.iter() {
+            if let Some(center) = positions.get(&node.id) {
+                let stroke_color = match node.kind {
+                    GraphNodeKind::Input | GraphNodeKind::Output => Color32::WHITE,
+                    GraphNodeKind::Hidden => Color32::GRAY,
+                };
+                painter.circle(
+                    *center,
+                    NODE_RADIUS,
+                    Self::color(node.value),
+                    Stroke::new(1.0, stroke_color),
+                );
+                if hover_pos.is_some_and(|pos| pos.distance(*center) <= 2.0 * NODE_RADIUS) {
+                    hovered = Some(node);
+                }
+            }
+        }
+
+        if let Some(node) = hovered {
+            let mut text = format!("{}\nvalue: {:.3}", node.label, node.value);
+            if let Some(activation) = node.activation {
+                text.push_str(&format!("\nactivation: {activation:?}"));
+            }
+            if let Some(bias) = node.bias {
+                text.push_str(&format!("\nbias: {bias:.3}"));
+            }
+            response.on_hover_text_at_pointer(text);
+        }
+    }
+
+    /// Places every layer in a column, with its nodes evenly spaced.
+    fn layout(graph: &NetworkGraph, rect: Rect) -> HashMap<usize, Pos2> {
+        let num_layers = graph
+            .nodes
+            .iter()
+            .map(|node| node.layer + 1)
+            .max()
+            .unwrap_or_default();
+        let mut positions = HashMap::new();
+        for layer in 0..num_layers {
+            let x = if num_layers > 1 {
+                rect.left() + rect.width() * layer as f32 / (num_layers - 1) as f32
+            } else {
+                rect.center().x
+            };
+            let nodes: Vec<_> = graph
+                .nodes
+                .iter()
+                .filter(|node| node.layer == layer)
+                .collect();
+            for (index, node) in nodes.iter().enumerate() {
+                let y = rect.top() + rect.height() * (index as f32 + 0.5) / nodes.len() as f32;
+                positions.insert(node.id, Pos2::new(x, y));
+            }
+        }
+        positions
+    }
+
+    fn color(value: Scalar) -> Color32 {
+        let intensity = (value.abs() as f32).min(1.0);
+        if value >= 0.0 {
+            Rgba::from_rgb(0.1, 0.3 * intensity, intensity).into()
+        } else {
+            Rgba::from_rgb(intensity, 0.2 * intensity, 0.1).into()
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use eframe::egui;
 
 use crate::app::Application;
+use crate::camera::CameraMode;
+use crate::world_panel::Tool;
 
 pub struct TopBar;
 
@@ -28,8 +30,27 @@ impl TopBar {
                     ui.label(format!("{:3.0} FPS", app.frames_per_second));
                     ui.separator();
 
-                    if ui.button("Selection").clicked() {
-                        println!("Selection")
+                    ui.label("Camera");
+                    ui.selectable_value(&mut app.camera.mode, CameraMode::Free, "Free");
+                    ui.selectable_value(&mut app.camera.mode, CameraMode::Fit, "Fit");
+                    ui.selectable_value(&mut app.camera.mode, CameraMode::Follow, "Follow");
+                    ui.separator();
+
+                    ui.toggle_value(&mut app.show_charts, "Charts");
+                    ui.toggle_value(&mut app.genome_editor.visible, "Genome");
+                    ui.separator();
+
+                    ui.label("Tool");
+                    for tool in Tool::ALL {
+                        ui.selectable_value(&mut app.tool, tool, tool.name());
+                    }
+                    ui.separator();
+
+                    if ui.button("Clone").clicked() {
+                        app.on_clone_button();
+                    }
+                    if ui.button("Delete").clicked() {
+                        app.on_delete_button();
                     }
                 });
             });
@@ -1,15 +1,56 @@
 use eframe::{
-    egui::{self, Color32, Painter, Pos2, Rect, Response, Rgba, Rounding, Sense, Shape, Stroke},
-    emath::{self, RectTransform},
+    egui::{
+        self, Color32, Painter, PointerButton, Pos2, Rect, Response, Rgba, Rounding, Sense, Shape,
+        Stroke,
+    },
+    emath::RectTransform,
     epaint::{CircleShape, RectShape},
 };
 use nalgebra::{Const, OPoint, UnitComplex};
 use num_traits::float::FloatConst;
 
-use vlife_simulator::{cell, Scalar, Vec2};
+use vlife_simulator::{cell, CellId, Scalar, Vec2};
 
 use crate::app::Application;
 
+/// Zoom factor per scrolled point, as an exponent.
+const ZOOM_SPEED: f32 = 0.002;
+
+/// What the primary button does in the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Tool {
+    /// Click to select a cell and drag to move the camera.
+    Pan,
+    /// Click to select a cell and drag to select all the cells in a box.
+    Select,
+    /// Drag a cell to move it.
+    Move,
+    /// Click to add a random cell.
+    Spawn,
+    /// Click a cell to remove it.
+    Delete,
+}
+
+impl Tool {
+    pub(crate) const ALL: [Tool; 5] = [
+        Tool::Pan,
+        Tool::Select,
+        Tool::Move,
+        Tool::Spawn,
+        Tool::Delete,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Tool::Pan => "Pan",
+            Tool::Select => "Select",
+            Tool::Move => "Move",
+            Tool::Spawn => "Spawn",
+            Tool::Delete => "Delete",
+        }
+    }
+}
+
 pub struct WorldPanel;
 
 impl WorldPanel {
@@ -26,19 +67,43 @@ impl WorldPanel {
     fn render_simulation(ui: &mut egui::Ui, app: &mut Application, dt: Scalar) {
         let (response, painter) = ui.allocate_painter(
             egui::Vec2::new(ui.available_width(), ui.available_height()),
-            Sense::hover().union(Sense::click()),
+            Sense::hover().union(Sense::click_and_drag()),
         );
 
+        Self::handle_camera(ui, app, &response);
+        let target = app
+            .selected_cell
+            .and_then(|cell_id| app.simulator.get_cell_object(cell_id))
+            .map(|object| object.position());
+        app.camera.update(response.rect, app.world_size, target);
+        let to_screen = app.camera.to_screen(response.rect);
+        let from_screen = to_screen.inverse();
+        let zoom = app.camera.zoom();
+
         let world_rect = Rect::from_min_max(
             Pos2::ZERO,
             Pos2::new(app.world_size.x as f32, app.world_size.y as f32),
         );
-        let margin = 0.5 * (response.rect.size() - world_rect.size());
-        let screen_rect = world_rect.translate(response.rect.min.to_vec2() + margin);
-        let to_screen = emath::RectTransform::from_to(world_rect, screen_rect);
-        let from_screen = to_screen.inverse();
+        let screen_rect = to_screen.transform_rect(world_rect);
+        painter.add(RectShape::stroke(
+            screen_rect.expand(1.0),
+            Rounding::none(),
+            Stroke::new(1.0, Color32::from_gray(128)),
+        ));
 
+        Self::handle_drag_released(app, &response);
         Self::handle_interactions(app, response, from_screen);
+        if let Some((start, end)) = app.selection_box {
+            let rect = Rect::from_two_pos(
+                start.transform_pos(&to_screen),
+                end.transform_pos(&to_screen),
+            );
+            painter.add(RectShape::stroke(
+                rect,
+                Rounding::none(),
+                Stroke::new(1.0, Color32::from_rgb(255, 180, 0)),
+            ));
+        }
 
         let size_fill_color = Rgba::from_white_alpha(0.0);
         let size_stroke_color = Rgba::from_white_alpha(0.1);
@@ -62,25 +127,21 @@ impl WorldPanel {
                 let energy_gain = (cell.energy_delta().max(0.0) / dt).min(1.0) as f32;
 
                 let fill_color = Rgba::from_rgb(energy_loss, energy, energy_gain);
-                let stroke_color = app
-                    .selected_cell
-                    .filter(|selected_id| *selected_id == cell_id)
-                    .map_or(normal_color, |_| selected_color);
-
-                painter.add(RectShape::stroke(
-                    screen_rect.expand(1.0),
-                    Rounding::none(),
-                    Stroke::new(1.0, Color32::from_gray(128)),
-                ));
+                let stroke_color = if app.selected_cells.contains(&cell_id) {
+                    selected_color
+                } else {
+                    normal_color
+                };
+
                 painter.add(CircleShape {
                     center: position.transform_pos(&to_screen),
-                    radius: cell.size() as f32,
+                    radius: cell.size() as f32 * zoom,
                     fill: size_fill_color.into(),
                     stroke: (1.0, size_stroke_color).into(),
                 });
                 painter.add(CircleShape {
                     center: position.transform_pos(&to_screen),
-                    radius: cell.contracted_size() as f32,
+                    radius: cell.contracted_size() as f32 * zoom,
                     fill: fill_color.into(),
                     stroke: (1.0, stroke_color).into(),
                 });
@@ -91,6 +152,7 @@ impl WorldPanel {
                     position,
                     cell.movement_direction(),
                     cell.contracted_size(),
+                    zoom,
                 );
 
                 painter.add(Shape::line_segment(
@@ -111,60 +173,139 @@ impl WorldPanel {
         }
     }
 
+    /// Zooms with the mouse wheel around the pointer, and pans dragging the world
+    /// with the secondary or middle buttons, or the primary one with the pan tool.
+    fn handle_camera(ui: &egui::Ui, app: &mut Application, response: &Response) {
+        if response.dragged_by(PointerButton::Secondary)
+            || response.dragged_by(PointerButton::Middle)
+            || (app.tool == Tool::Pan && response.dragged_by(PointerButton::Primary))
+        {
+            app.camera.pan(response.drag_delta());
+        }
+        if let Some(pos) = response.hover_pos() {
+            let scroll = ui.input(|input| input.scroll_delta.y);
+            if scroll != 0.0 {
+                app.camera
+                    .zoom_at(response.rect, pos, (scroll * ZOOM_SPEED).exp());
+            }
+        }
+    }
+
     fn handle_interactions(app: &mut Application, response: Response, from_screen: RectTransform) {
-        // if let Some(pos) = response.hover_pos() {
-        // println!("H: {:?} {}", pos, response.hovered());
-        // }
-        if let Some(pos) = response.interact_pointer_pos() {
-            // println!("I: {:?} {} {}", pos, response.clicked(), response.dragged());
-            if response.clicked() {
-                let pos = from_screen.transform_pos(pos);
-                if let Some(cell_id) = app
-                    .simulator
-                    .get_cell_id_closer_to(pos.x as Scalar, pos.y as Scalar)
-                {
+        let Some(pos) = response.interact_pointer_pos() else {
+            return;
+        };
+        let pos = from_screen.transform_pos(pos);
+        let pos = Vec2::new(pos.x as Scalar, pos.y as Scalar);
+        match app.tool {
+            Tool::Pan | Tool::Select => {
+                if response.clicked() {
+                    if let Some(cell_id) = app.simulator.get_cell_id_closer_to(pos.x, pos.y) {
+                        app.on_cell_selected(cell_id);
+                    }
+                }
+                if app.tool == Tool::Select && response.dragged_by(PointerButton::Primary) {
+                    let start = app.selection_box.map_or(pos, |(start, _)| start);
+                    app.selection_box = Some((start, pos));
+                }
+            }
+            Tool::Move => {
+                if response.drag_started_by(PointerButton::Primary) {
+                    app.dragged_cell = Self::cell_at(app, pos);
+                    if let Some(cell_id) = app.dragged_cell {
+                        app.on_cell_selected(cell_id);
+                    }
+                }
+                if let Some(cell_id) = app.dragged_cell {
+                    app.simulator.move_cell(cell_id, pos);
+                }
+            }
+            Tool::Spawn => {
+                if response.clicked() {
+                    let cell_id = app.simulator.add_random_cell_at(pos);
                     app.on_cell_selected(cell_id);
                 }
             }
+            Tool::Delete => {
+                if response.clicked() {
+                    if let Some(cell_id) = Self::cell_at(app, pos) {
+                        app.simulator.remove_cell(cell_id);
+                        app.selected_cells.retain(|selected| *selected != cell_id);
+                        app.selected_cell = app.selected_cells.first().copied();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finishes dragging cells and boxes when the primary button is released.
+    fn handle_drag_released(app: &mut Application, response: &Response) {
+        if !response.drag_released() {
+            return;
+        }
+        app.dragged_cell = None;
+        if let Some((start, end)) = app.selection_box.take() {
+            let (min, max) = (start.inf(&end), start.sup(&end));
+            let cell_ids = app
+                .simulator
+                .cells()
+                .filter_map(|(cell_id, _)| app.simulator.get_cell_view(cell_id))
+                .filter(|cell| {
+                    let position = cell.position();
+                    position >= min && position <= max
+                })
+                .map(|cell| cell.id())
+                .collect();
+            app.on_cells_selected(cell_ids);
         }
     }
 
+    /// The cell whose body contains the position.
+    fn cell_at(app: &Application, pos: Vec2) -> Option<CellId> {
+        app.simulator
+            .get_cell_id_closer_to(pos.x, pos.y)
+            .and_then(|cell_id| app.simulator.get_cell_view(cell_id))
+            .filter(|cell| (cell.position() - pos).norm() <= cell.radius())
+            .map(|cell| cell.id())
+    }
+
     fn paint_eyes(
         painter: &Painter,
         to_screen: &RectTransform,
         position: Vec2,
         direction: Scalar,
         contracted_size: Scalar,
+        zoom: f32,
     ) {
         let left_eye_angle = UnitComplex::new(-direction + 0.1 * Scalar::PI());
         let right_eye_angle = UnitComplex::new(-direction - 0.1 * Scalar::PI());
         let left_eye_vec = left_eye_angle.transform_vector(&Vec2::x_axis());
-        let right_eye_vec = right_eye_angle.transform_vector(&&Vec2::x_axis());
+        let right_eye_vec = right_eye_angle.transform_vector(&Vec2::x_axis());
         let white_eye_distance = 0.8 * contracted_size;
-        let white_eye_size = 0.25 * contracted_size as f32;
+        let white_eye_size = 0.25 * contracted_size as f32 * zoom;
         let pupil_eye_distance = 0.78 * contracted_size;
-        let pupil_eye_size = 0.15 * contracted_size as f32;
+        let pupil_eye_size = 0.15 * contracted_size as f32 * zoom;
 
         painter.add(CircleShape {
-            center: (left_eye_vec * white_eye_distance + position).transform_pos(&to_screen),
+            center: (left_eye_vec * white_eye_distance + position).transform_pos(to_screen),
             radius: white_eye_size,
             fill: Color32::WHITE,
             stroke: Stroke::NONE,
         });
         painter.add(CircleShape {
-            center: (left_eye_vec * pupil_eye_distance + position).transform_pos(&to_screen),
+            center: (left_eye_vec * pupil_eye_distance + position).transform_pos(to_screen),
             radius: pupil_eye_size,
             fill: Color32::BLACK,
             stroke: Stroke::NONE,
         });
         painter.add(CircleShape {
-            center: (right_eye_vec * white_eye_distance + position).transform_pos(&to_screen),
+            center: (right_eye_vec * white_eye_distance + position).transform_pos(to_screen),
             radius: white_eye_size,
             fill: Color32::WHITE,
             stroke: Stroke::NONE,
         });
         painter.add(CircleShape {
-            center: (right_eye_vec * pupil_eye_distance + position).transform_pos(&to_screen),
+            center: (right_eye_vec * pupil_eye_distance + position).transform_pos(to_screen),
             radius: pupil_eye_size,
             fill: Color32::BLACK,
             stroke: Stroke::NONE,
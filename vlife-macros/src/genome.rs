@@ -1,45 +1,110 @@
 use proc_macro2::{Literal, TokenStream};
 use quote::quote;
-use syn::{Data, DeriveInput, Error};
+use syn::{Data, DataStruct, DeriveInput, Error, Ident};
 
-const BUILD_GENOME_ATTR_IDENT: &'static str = "build_genome";
+const BUILD_GENOME_ATTR_IDENT: &str = "build_genome";
+
+enum GenomeField {
+    Nested(Ident, Literal),
+    /// Nested genes that can't be blended, like flags or categories.
+    Discrete(Ident, Literal),
+    Gen(Ident, Literal),
+}
 
 pub(crate) fn derive_build_genome(input: DeriveInput) -> syn::Result<TokenStream> {
     let ident = input.ident.clone();
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
 
-    let data = if let Data::Struct(data) = input.data {
+    let tokens = parse_genome_fields(struct_data(&input)?)?
+        .into_iter()
+        .map(|field| match field {
+            GenomeField::Nested(field_ident, field_literal) => quote!(
+                self.#field_ident.build_genome(builder.nested(#field_literal));
+            ),
+            GenomeField::Discrete(field_ident, field_literal) => quote!(
+                self.#field_ident.build_genome(builder.nested(#field_literal).discrete());
+            ),
+            GenomeField::Gen(field_ident, field_literal) => quote!(
+                builder.add(#field_literal, crate::genome::Gen::new(self.#field_ident));
+            ),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(quote! {
+      impl #impl_generics crate::genome::BuildGenome for #ident #ty_generics #where_clause {
+        fn build_genome(&self, builder: crate::genome::GenomeBuilder) {
+          #(#tokens)*
+        }
+      }
+    })
+}
+
+pub(crate) fn derive_apply_genome(input: DeriveInput) -> syn::Result<TokenStream> {
+    let ident = input.ident.clone();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let tokens = parse_genome_fields(struct_data(&input)?)?
+        .into_iter()
+        .map(|field| match field {
+            GenomeField::Nested(field_ident, field_literal)
+            | GenomeField::Discrete(field_ident, field_literal) => quote!(
+                self.#field_ident.apply_genome(reader.nested(#field_literal));
+            ),
+            GenomeField::Gen(field_ident, field_literal) => quote!(
+                if let Some(value) = reader.get(#field_literal) {
+                    self.#field_ident = value;
+                }
+            ),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(quote! {
+      impl #impl_generics crate::genome::ApplyGenome for #ident #ty_generics #where_clause {
+        fn apply_genome(&mut self, reader: crate::genome::GenomeReader<'_>) {
+          #(#tokens)*
+        }
+      }
+    })
+}
+
+fn struct_data(input: &DeriveInput) -> syn::Result<&DataStruct> {
+    if let Data::Struct(data) = &input.data {
         Ok(data)
     } else {
         Err(Error::new_spanned(
             input.clone(),
             "Only structs are allowed",
         ))
-    }?;
+    }
+}
 
-    let mut tokens = Vec::new();
-    for field in data.fields {
+fn parse_genome_fields(data: &DataStruct) -> syn::Result<Vec<GenomeField>> {
+    let mut fields = Vec::new();
+    for field in data.fields.iter() {
         let field_ident = field.ident.as_ref().ok_or(Error::new_spanned(
             field.clone(),
             "Only named fields are supported",
         ))?;
         let field_literal = Literal::string(field_ident.to_string().as_str());
-        for attr in field.attrs {
+        for attr in field.attrs.iter() {
             if attr.path().is_ident(BUILD_GENOME_ATTR_IDENT) {
                 attr.parse_nested_meta(|meta| {
                     let path = &meta.path;
                     if path.is_ident("nested") {
-                        tokens.push(quote!(
-                            self.#field_ident.build_genome(builder.nested(#field_literal));
+                        fields.push(GenomeField::Nested(
+                            field_ident.clone(),
+                            field_literal.clone(),
                         ));
                         Ok(())
-                    } else if path.is_ident("gen") {
-                        tokens.push(quote!(
-                            builder.add(#field_literal, crate::genome::Gen {
-                                value: self.#field_ident,
-                            });
+                    } else if path.is_ident("discrete") {
+                        fields.push(GenomeField::Discrete(
+                            field_ident.clone(),
+                            field_literal.clone(),
                         ));
                         Ok(())
+                    } else if path.is_ident("gen") {
+                        fields.push(GenomeField::Gen(field_ident.clone(), field_literal.clone()));
+                        Ok(())
                     } else {
                         Err(Error::new_spanned(attr.clone(), "Wrong attribute argument"))
                     }
@@ -47,12 +112,5 @@ pub(crate) fn derive_build_genome(input: DeriveInput) -> syn::Result<TokenStream
             }
         }
     }
-
-    Ok(quote! {
-      impl #impl_generics crate::genome::BuildGenome for #ident #ty_generics #where_clause {
-        fn build_genome(&self, builder: crate::genome::GenomeBuilder) {
-          #(#tokens)*
-        }
-      }
-    })
+    Ok(fields)
 }
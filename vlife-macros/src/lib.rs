@@ -10,3 +10,11 @@ pub fn derive_build_genome(input: TokenStream) -> TokenStream {
         .unwrap_or_else(syn::Error::into_compile_error)
         .into()
 }
+
+#[proc_macro_derive(ApplyGenome, attributes(build_genome))]
+pub fn derive_apply_genome(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    genome::derive_apply_genome(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,47 @@
+//! Compares the time of updating the simulator with and without the batched evaluation
+//! of the neuronal networks, both for random cells and for copies of the same cell.
+//!
+//! Run it with `cargo run --release --example batch_evaluation`.
+
+use std::time::{Duration, Instant};
+
+use vlife_simulator::{Simulator, Vec2};
+
+const STEPS: usize = 50;
+const TIME_STEP: f64 = 1.0 / 60.0;
+
+fn run(num_cells: usize, copies: bool, batch_evaluation: bool) -> Duration {
+    let size = 40.0 * (num_cells as f64).sqrt();
+    let mut simulator = Simulator::new(Vec2::new(size, size))
+        .with_seed(0)
+        .with_batch_evaluation(batch_evaluation);
+    if copies {
+        let cell_id = simulator.add_random_cell();
+        let genome = simulator.get_cell_view(cell_id).unwrap().genome();
+        for index in 1..num_cells {
+            let position = Vec2::new((index % 40) as f64 + 0.5, (index / 40) as f64 + 0.5);
+            simulator.add_cell_with_genome(&genome, position * size / 40.0, Some(cell_id));
+        }
+    } else {
+        (0..num_cells).for_each(|_| {
+            simulator.add_random_cell();
+        });
+    }
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        simulator.update(TIME_STEP);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    println!("cells   kind    one by one  batched");
+    for num_cells in [500, 2000] {
+        for copies in [false, true] {
+            let kind = if copies { "copies" } else { "random" };
+            let single = run(num_cells, copies, false);
+            let batched = run(num_cells, copies, true);
+            println!("{num_cells:5}  {kind}  {single:10.3?}  {batched:7.3?}");
+        }
+    }
+}
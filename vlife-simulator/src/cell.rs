@@ -3,8 +3,13 @@ use num_traits::float::FloatConst;
 use rand::Rng;
 use std::ops::Neg;
 
-use vlife_macros::BuildGenome;
+use vlife_macros::{ApplyGenome, BuildGenome};
 
+use crate::events::DeathCause;
+use crate::genome::{ApplyGenome, BuildGenome, Genome, GenomeBuilder};
+use crate::lineage::Lineage;
+use crate::network_graph::NetworkGraph;
+use crate::neurons::BrainType;
 use crate::physics::{Object, ObjectId, Physics};
 use crate::{neurons::Neurons, simulator::SimulationContext, V};
 use crate::{Scalar, Vec2};
@@ -25,12 +30,15 @@ pub const NEURON_COST: Scalar = 0.0000005;
 pub const MOVEMENT_COST: Scalar = 0.0001;
 pub const CONTRACTION_COST: Scalar = 0.0001;
 pub const DIVISION_COST: Scalar = 0.001;
+pub const MATING_COST: Scalar = 0.001;
 
 /// Model for a cell.
-#[derive(BuildGenome)]
+#[derive(BuildGenome, ApplyGenome)]
 pub struct Cell {
     /// Reference to the Physics object.
     pub(crate) object_id: ObjectId,
+    /// Ancestry of the cell. This is assigned when the cell is added to the simulation.
+    pub(crate) lineage: Lineage,
 
     /// Neuronal network.
     /// This is used to model complex behaviour based on external/internal signals.
@@ -111,17 +119,19 @@ pub struct Cell {
     pub(crate) contact_count: Scalar,
     /// The normal of all the contacts.
     pub(crate) contact_normal: Vec2,
+    /// Energy exchanged with other cells in the last contacts.
+    pub(crate) contact_energy_delta: Scalar,
 
     pub(crate) stats: CellStats,
 }
 
 impl Cell {
-    pub fn random(object_id: ObjectId, size: Scalar) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng>(rng: &mut R, object_id: ObjectId, size: Scalar) -> Self {
         let area = Scalar::PI() * size * size;
         Self {
             object_id,
-            neurons: Neurons::random(),
+            lineage: Lineage::default(),
+            neurons: Neurons::random(rng),
             age: 0.0,
             size,
             area,
@@ -147,6 +157,7 @@ impl Cell {
             contact_energy_absorption_amount: 0.0,
             contact_count: 0.0,
             contact_normal: Vec2::zeros(),
+            contact_energy_delta: 0.0,
             stats: CellStats::default(),
         }
     }
@@ -159,7 +170,12 @@ impl Cell {
     ) -> Cell {
         Self {
             object_id,
-            neurons: cell.neurons.clone(),
+            lineage: Lineage::default(),
+            neurons: {
+                let mut neurons = cell.neurons.clone();
+                neurons.reset_learning();
+                neurons
+            },
             age: 0.0,
             size: cell.size,
             area: cell.area,
@@ -183,10 +199,70 @@ impl Cell {
             contact_energy_absorption_amount: 0.0,
             contact_count: 0.0,
             contact_normal: Vec2::zeros(),
+            contact_energy_delta: 0.0,
             stats: CellStats::default(),
         }
     }
 
+    pub fn from_genome<R: Rng>(rng: &mut R, object_id: ObjectId, genome: &Genome) -> Cell {
+        let mut cell = Self::random(rng, object_id, MAX_SIZE);
+        cell.set_genome(genome);
+        cell
+    }
+
+    pub fn offspring_from<R: Rng>(
+        rng: &mut R,
+        object_id: ObjectId,
+        genome: &Genome,
+        energy: Scalar,
+        molecules: V<NUM_MOLECULES>,
+    ) -> Cell {
+        let mut cell = Self::from_genome(rng, object_id, genome);
+        cell.energy = energy;
+        cell.last_energy = energy;
+        cell.molecules = molecules;
+        cell.division_grow_factor = cell.size.recip();
+        cell
+    }
+
+    pub fn genome(&self) -> Genome {
+        let builder = GenomeBuilder::new();
+        self.build_genome(builder.clone());
+        builder.build()
+    }
+
+    /// Applies the genes to the cell, and updates the state that depends on them.
+    pub fn set_genome(&mut self, genome: &Genome) {
+        self.apply_genome(genome.reader());
+        self.area = Scalar::PI() * self.size * self.size;
+        self.neurons.update_structure();
+    }
+
+    pub fn brain_type(&self) -> BrainType {
+        self.neurons.brain_type()
+    }
+
+    pub fn neurons(&self) -> &Neurons {
+        &self.neurons
+    }
+
+    /// Snapshot of the neuronal network, to inspect or export it.
+    pub fn network_graph(&self) -> NetworkGraph {
+        self.neurons.graph()
+    }
+
+    pub fn lineage(&self) -> &Lineage {
+        &self.lineage
+    }
+
+    pub fn stats(&self) -> &CellStats {
+        &self.stats
+    }
+
+    pub fn age(&self) -> Scalar {
+        self.age
+    }
+
     pub fn energy(&self) -> Scalar {
         self.energy
     }
@@ -229,9 +305,33 @@ impl Cell {
         self.area * DIVISION_COST
     }
 
+    pub fn should_mate(&self) -> bool {
+        self.neurons.get_mate() > 0.0
+            && self.energy >= self.mating_cost()
+            && self.division_energy_reserve >= 0.5 * self.division_threshold
+            && self.division_grow_factor >= 1.0
+    }
+
+    pub(crate) fn mating_cost(&self) -> Scalar {
+        self.area * MATING_COST
+    }
+
     pub fn is_dead(&self) -> bool {
-        self.energy + self.stored_energy <= ALIVE_ENERGY_THRESHOLD
-            || self.zero_energy_time >= self.zero_energy_limit
+        self.death_cause().is_some()
+    }
+
+    pub fn death_cause(&self) -> Option<DeathCause> {
+        if self.energy + self.stored_energy <= ALIVE_ENERGY_THRESHOLD {
+            if self.contact_energy_delta < 0.0 {
+                Some(DeathCause::Predation)
+            } else {
+                Some(DeathCause::Starvation)
+            }
+        } else if self.zero_energy_time >= self.zero_energy_limit {
+            Some(DeathCause::ZeroEnergyTimeout)
+        } else {
+            None
+        }
     }
 
     pub fn energy_diffusion(&self) -> Scalar {
@@ -261,6 +361,7 @@ impl Cell {
 
     pub fn on_cell_contact(&mut self, energy_delta: Scalar, normal: Vec2) {
         self.energy += energy_delta;
+        self.contact_energy_delta += energy_delta;
         if energy_delta > 0.0 {
             self.stats.update_energy_absorbed_in(energy_delta);
         } else if energy_delta < 0.0 {
@@ -271,11 +372,30 @@ impl Cell {
     }
 
     pub fn update(&mut self, dt: Scalar, context: SimulationContext<'_>) {
+        let energy_delta = self.sense(dt, &context);
+        self.neurons.evaluate();
+        self.act(dt, energy_delta, context);
+    }
+
+    /// First part of the update, before evaluating the neuronal network.
+    /// Sets and prepares its inputs, and returns the energy delta of the step.
+    pub(crate) fn sense(&mut self, dt: Scalar, context: &SimulationContext<'_>) -> Scalar {
         let energy_delta = self.energy - self.last_energy;
         self.last_energy = self.energy;
         self.age += dt;
+        self.stats
+            .update_distance_travelled(context.object.velocity().magnitude() * dt);
+
+        self.set_neurons_inputs(energy_delta, context);
+        self.neurons.prepare_inputs();
+        energy_delta
+    }
 
-        self.process_neurons(dt, energy_delta, &context);
+    /// Last part of the update, using the outputs of the neuronal network.
+    pub(crate) fn act(&mut self, dt: Scalar, energy_delta: Scalar, context: SimulationContext<'_>) {
+        if context.plasticity {
+            self.neurons.learn(energy_delta);
+        }
 
         let basal_energy = self.basal_energy().min(self.energy);
         self.energy -= basal_energy;
@@ -294,7 +414,7 @@ impl Cell {
         }
     }
 
-    fn process_neurons(&mut self, _dt: Scalar, energy_delta: Scalar, context: &SimulationContext) {
+    fn set_neurons_inputs(&mut self, energy_delta: Scalar, context: &SimulationContext) {
         // self.neurons.set_velocity_pos(&context.object.velocity());
         self.neurons
             .set_velocity_magnitude(context.object.velocity().magnitude());
@@ -338,8 +458,6 @@ impl Cell {
             self.neurons.set_contact_normal(&Vec2::zeros());
             self.neurons.set_contact_normal_magnitude(0.0);
         }
-
-        self.neurons.process();
     }
 
     fn compute_contraction(&mut self, dt: Scalar) {
@@ -446,12 +564,27 @@ impl Cell {
         self.division_energy_reserve = 0.0;
         let molecules = self.molecules * 0.5;
         self.molecules = molecules;
+        self.stats.update_offspring();
         Cell::child_from(new_object_id, self, energy_reserve, molecules)
     }
+
+    /// Pays this parent's share of the mating cost, and gives away the energy
+    /// reserved for the division and a quarter of the molecules for the offspring.
+    pub(crate) fn mating_contribution(&mut self, cost: Scalar) -> (Scalar, V<NUM_MOLECULES>) {
+        let cost = cost.min(self.energy);
+        self.energy -= cost;
+        self.stats.update_energy_consumed(cost);
+        let energy_reserve = self.division_energy_reserve;
+        self.division_energy_reserve = 0.0;
+        let molecules = self.molecules * 0.25;
+        self.molecules -= molecules;
+        self.stats.update_offspring();
+        (energy_reserve, molecules)
+    }
 }
 //
 // impl BuildGenome for Cell {
-//     fn build_genome<'a>(&self, genome: GenomeBuilder) {
+//     fn build_genome(&self, genome: GenomeBuilder) {
 //         self.neurons.build_genome(genome.nested("neurons"));
 //         genome.add("size", Gen { value: self.size });
 //     }
@@ -470,6 +603,11 @@ impl std::fmt::Display for Cell {
             "Age> Days: {:.0}, Time: {:02.0}:{:02.0}:{:04.1}",
             days, hours, minutes, seconds
         )?;
+        writeln!(
+            f,
+            "Lineage> Id: {}, Generation: {}, Parents: {:?}",
+            self.lineage.lineage_id, self.lineage.generation, self.lineage.parents,
+        )?;
         writeln!(
             f,
             "Energy> Available: {:6.2} ({:3.0} %), Stored: {:6.2} ({:3.0} %), Delta: {:7.4}, Basal: {:7.4} Zero: {:5.1} / {:5.1}",
@@ -534,13 +672,15 @@ impl std::fmt::Display for Cell {
         let energy_negative = self.stats.energy_consumed + self.stats.energy_absorbed_out;
         writeln!(
             f,
-            "Stats> Energy Consumed: {:5.1}, Produced: {:5.1}, Absorbed Out: {:5.1}, Absorbed In: {:5.1}, Net: {:5.1}, Ratio: {:6.3}",
+            "Stats> Energy Consumed: {:5.1}, Produced: {:5.1}, Absorbed Out: {:5.1}, Absorbed In: {:5.1}, Net: {:5.1}, Ratio: {:6.3}, Offspring: {}, Distance: {:6.1}",
             self.stats.energy_consumed,
             self.stats.energy_produced,
             self.stats.energy_absorbed_out,
             self.stats.energy_absorbed_in,
             energy_positive - energy_negative,
-            (1.0 + energy_positive) / (1.0 + energy_negative),
+            self.stats.energy_ratio(),
+            self.stats.offspring,
+            self.stats.distance_travelled,
         )?;
         write!(f, "{}", self.neurons)?;
         Ok(())
@@ -553,9 +693,19 @@ pub struct CellStats {
     pub energy_produced: Scalar,
     pub energy_absorbed_out: Scalar,
     pub energy_absorbed_in: Scalar,
+    /// Number of cells born by division or mating.
+    pub offspring: usize,
+    pub distance_travelled: Scalar,
 }
 
 impl CellStats {
+    /// Ratio between the energy gained and the energy lost.
+    pub fn energy_ratio(&self) -> Scalar {
+        let energy_positive = self.energy_produced + self.energy_absorbed_in;
+        let energy_negative = self.energy_consumed + self.energy_absorbed_out;
+        (1.0 + energy_positive) / (1.0 + energy_negative)
+    }
+
     fn update_energy_consumed(&mut self, amount: Scalar) {
         self.energy_consumed += amount;
     }
@@ -571,4 +721,12 @@ impl CellStats {
     fn update_energy_absorbed_in(&mut self, amount: Scalar) {
         self.energy_absorbed_in += amount;
     }
+
+    fn update_offspring(&mut self) {
+        self.offspring += 1;
+    }
+
+    fn update_distance_travelled(&mut self, distance: Scalar) {
+        self.distance_travelled += distance;
+    }
 }
@@ -1,46 +1,305 @@
-use ordered_float::NotNan;
 use rand::Rng;
-use std::collections::BTreeMap;
 
-use crate::cell::Cell;
-use crate::genome::{BuildGenome, Genome, GenomeBuilder};
+use crate::genome::Genome;
+use crate::lineage::Lineage;
+use crate::selection::SelectionStrategy;
+use crate::species::SpeciesId;
 use crate::Scalar;
 
+/// A dead cell remembered by the rank.
+#[derive(Debug, Clone)]
+pub struct RankEntry {
+    pub genome: Genome,
+    pub lineage: Lineage,
+    /// Fitness score when the cell died.
+    pub score: Scalar,
+    /// Age of the cell when it died.
+    pub lifespan: Scalar,
+    /// Species of the cell when it died.
+    pub species_id: Option<SpeciesId>,
+    /// Simulation time when the entry was inserted.
+    pub inserted: Scalar,
+    /// Number of times the genome was reinserted unchanged into the simulation.
+    pub reinsertions: usize,
+}
+
+impl RankEntry {
+    /// Time since the entry was inserted.
+    pub fn age(&self, time: Scalar) -> Scalar {
+        (time - self.inserted).max(0.0)
+    }
+}
+
+/// How the entries of the rank get old.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RankAging {
+    /// Exponential decay rate (per unit of simulation time) applied to the scores.
+    /// Negative scores are decayed towards more negative values.
+    pub decay_rate: Scalar,
+    /// Entries older than this are removed from the rank.
+    pub max_age: Option<Scalar>,
+}
+
+/// Hall of fame with the genomes of the best dead cells.
 pub struct CellRank {
-    cells: BTreeMap<NotNan<Scalar>, Cell>,
+    entries: Vec<RankEntry>,
     max_size: usize,
+    time: Scalar,
+    selection_strategy: SelectionStrategy,
+    aging: RankAging,
+    /// Number of best entries that are reinserted unchanged before using recombination.
+    elite_size: usize,
 }
 
 impl CellRank {
     pub fn new(max_size: usize) -> Self {
         Self {
-            cells: BTreeMap::default(),
+            entries: Vec::new(),
             max_size,
+            time: 0.0,
+            selection_strategy: SelectionStrategy::default(),
+            aging: RankAging::default(),
+            elite_size: 0,
         }
     }
 
-    pub fn choose_random_genome(&self) -> Option<Genome> {
-        let mut rng = rand::thread_rng();
-        if !self.cells.is_empty() {
-            let drop = rng.gen_range(0..self.cells.len());
-            if let Some(cell) = self.cells.values().skip(drop).next() {
-                let builder = GenomeBuilder::new();
-                cell.build_genome(builder.clone());
-                let genome = builder.build();
-                Some(genome)
-            } else {
-                None
-            }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &RankEntry> + '_ {
+        self.entries.iter()
+    }
+
+    pub fn selection_strategy(&self) -> SelectionStrategy {
+        self.selection_strategy
+    }
+
+    pub fn set_selection_strategy(&mut self, strategy: SelectionStrategy) {
+        self.selection_strategy = strategy;
+    }
+
+    pub fn aging(&self) -> RankAging {
+        self.aging
+    }
+
+    pub fn set_aging(&mut self, aging: RankAging) {
+        self.aging = aging;
+    }
+
+    pub fn elite_size(&self) -> usize {
+        self.elite_size
+    }
+
+    pub fn set_elite_size(&mut self, elite_size: usize) {
+        self.elite_size = elite_size;
+    }
+
+    /// Score of the entry after applying the decay for its age.
+    pub fn effective_score(&self, entry: &RankEntry) -> Scalar {
+        let factor = (-self.aging.decay_rate * entry.age(self.time)).exp();
+        if entry.score >= 0.0 {
+            entry.score * factor
         } else {
-            None
+            entry.score / factor
         }
     }
 
-    pub fn insert(&mut self, score: Scalar, cell: Cell) {
-        let score = NotNan::new(score).expect("non-nan-score");
-        self.cells.insert(score, cell);
-        if self.cells.len() > self.max_size {
-            self.cells.pop_first();
+    /// Updates the time of the rank, and removes the entries that got too old.
+    pub fn update(&mut self, time: Scalar) {
+        self.time = time;
+        if let Some(max_age) = self.aging.max_age {
+            self.entries.retain(|entry| entry.age(time) <= max_age);
         }
     }
+
+    pub fn choose_random_entry<R: Rng>(&self, rng: &mut R) -> Option<&RankEntry> {
+        self.choose_random_entry_matching(rng, |_| true)
+    }
+
+    /// Chooses an entry using the selection strategy, but only between the ones matching the filter.
+    pub fn choose_random_entry_matching<R: Rng>(
+        &self,
+        rng: &mut R,
+        filter: impl Fn(&RankEntry) -> bool,
+    ) -> Option<&RankEntry> {
+        let mut sorted = self.sorted_indices();
+        sorted.retain(|index| filter(&self.entries[*index]));
+        let scores = sorted
+            .iter()
+            .map(|index| self.effective_score(&self.entries[*index]))
+            .collect::<Vec<_>>();
+        self.selection_strategy
+            .choose(rng, &scores)
+            .map(|position| &self.entries[sorted[position]])
+    }
+
+    /// Chooses the best of the elite entries that was not reinserted yet.
+    pub fn take_elite(&mut self) -> Option<&RankEntry> {
+        let elite = self
+            .sorted_indices()
+            .into_iter()
+            .rev()
+            .take(self.elite_size)
+            .find(|index| self.entries[*index].reinsertions == 0);
+        elite.map(|index| {
+            let entry = &mut self.entries[index];
+            entry.reinsertions += 1;
+            &*entry
+        })
+    }
+
+    pub fn scores(&self) -> impl Iterator<Item = Scalar> + '_ {
+        self.entries.iter().map(|entry| entry.score)
+    }
+
+    /// Inserts a new entry, removing the worst one when the rank is full.
+    /// Entries with a NaN score are rejected.
+    pub fn insert(
+        &mut self,
+        score: Scalar,
+        genome: Genome,
+        lineage: Lineage,
+        lifespan: Scalar,
+        species_id: Option<SpeciesId>,
+    ) -> bool {
+        if score.is_nan() {
+            return false;
+        }
+        self.entries.push(RankEntry {
+            genome,
+            lineage,
+            score,
+            lifespan,
+            species_id,
+            inserted: self.time,
+            reinsertions: 0,
+        });
+        if self.entries.len() > self.max_size {
+            let worst = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by(|(_, entry1), (_, entry2)| self.compare_entries(entry1, entry2))
+                .map(|(index, _)| index);
+            if let Some(index) = worst {
+                self.entries.swap_remove(index);
+            }
+        }
+        true
+    }
+
+    /// Indices of the entries sorted in ascending order by effective score, and then by insertion time.
+    fn sorted_indices(&self) -> Vec<usize> {
+        let mut sorted = (0..self.entries.len()).collect::<Vec<_>>();
+        sorted.sort_by(|index1, index2| {
+            self.compare_entries(&self.entries[*index1], &self.entries[*index2])
+        });
+        sorted
+    }
+
+    /// On ties, the older entries are considered worse.
+    fn compare_entries(&self, entry1: &RankEntry, entry2: &RankEntry) -> std::cmp::Ordering {
+        self.effective_score(entry1)
+            .total_cmp(&self.effective_score(entry2))
+            .then(entry1.inserted.total_cmp(&entry2.inserted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genome::GenomeBuilder;
+
+    /// Inserts an entry whose lifespan identifies it.
+    fn insert(rank: &mut CellRank, score: Scalar, id: Scalar) -> bool {
+        let genome = GenomeBuilder::new().build();
+        rank.insert(score, genome, Lineage::default(), id, None)
+    }
+
+    fn sorted_ids(rank: &CellRank) -> Vec<Scalar> {
+        rank.sorted_indices()
+            .into_iter()
+            .map(|index| rank.entries[index].lifespan)
+            .collect()
+    }
+
+    #[test]
+    fn ties_are_ordered_by_insertion_time() {
+        let mut rank = CellRank::new(2);
+        insert(&mut rank, 1.0, 0.0);
+        rank.update(1.0);
+        insert(&mut rank, 1.0, 1.0);
+        assert_eq!(sorted_ids(&rank), [0.0, 1.0]);
+
+        // The oldest of the tied entries is removed
+        rank.update(2.0);
+        insert(&mut rank, 2.0, 2.0);
+        assert_eq!(sorted_ids(&rank), [1.0, 2.0]);
+    }
+
+    #[test]
+    fn nan_scores_are_rejected() {
+        let mut rank = CellRank::new(2);
+        assert!(!insert(&mut rank, Scalar::NAN, 0.0));
+        assert!(rank.is_empty());
+        assert!(insert(&mut rank, -1.0, 1.0));
+        assert_eq!(rank.len(), 1);
+    }
+
+    #[test]
+    fn scores_decay_with_age() {
+        let mut rank = CellRank::new(2);
+        rank.set_aging(RankAging {
+            decay_rate: 0.5,
+            max_age: None,
+        });
+        insert(&mut rank, 2.0, 0.0);
+        insert(&mut rank, -2.0, 1.0);
+        let (positive, negative) = (rank.entries[0].clone(), rank.entries[1].clone());
+        assert_eq!(rank.effective_score(&positive), 2.0);
+
+        rank.update(2.0);
+        let factor = (-1.0 as Scalar).exp();
+        assert!((rank.effective_score(&positive) - 2.0 * factor).abs() < 1e-12);
+        assert!((rank.effective_score(&negative) + 2.0 / factor).abs() < 1e-12);
+        // The raw scores are kept
+        assert_eq!(rank.scores().collect::<Vec<_>>(), [2.0, -2.0]);
+    }
+
+    #[test]
+    fn entries_older_than_max_age_are_removed() {
+        let mut rank = CellRank::new(3);
+        rank.set_aging(RankAging {
+            decay_rate: 0.0,
+            max_age: Some(5.0),
+        });
+        insert(&mut rank, 1.0, 0.0);
+        rank.update(3.0);
+        insert(&mut rank, 1.0, 1.0);
+
+        rank.update(5.0);
+        assert_eq!(rank.len(), 2);
+        rank.update(6.0);
+        assert_eq!(sorted_ids(&rank), [1.0]);
+    }
+
+    #[test]
+    fn elite_entries_are_taken_once_from_the_best() {
+        let mut rank = CellRank::new(3);
+        rank.set_elite_size(2);
+        insert(&mut rank, 3.0, 0.0);
+        insert(&mut rank, 1.0, 1.0);
+        insert(&mut rank, 2.0, 2.0);
+
+        assert_eq!(rank.take_elite().map(|entry| entry.lifespan), Some(0.0));
+        assert_eq!(rank.take_elite().map(|entry| entry.lifespan), Some(2.0));
+        assert!(rank.take_elite().is_none());
+        assert_eq!(rank.entries[0].reinsertions, 1);
+        assert_eq!(rank.entries[1].reinsertions, 0);
+    }
 }
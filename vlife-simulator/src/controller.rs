@@ -0,0 +1,16 @@
+use crate::neurons::Neurons;
+
+/// Hand-coded replacement of the neuronal network of a cell, to compare it with the evolved ones.
+///
+/// On every step it receives the neurons with the inputs already set, which can be read by name
+/// with [`Neurons::input`], and sets the outputs with [`Neurons::set_output`].
+/// The outputs start at zero on every step.
+pub trait Controller {
+    fn control(&mut self, neurons: &mut Neurons);
+}
+
+impl<F: FnMut(&mut Neurons)> Controller for F {
+    fn control(&mut self, neurons: &mut Neurons) {
+        self(neurons)
+    }
+}
@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::neurons::Neurons;
+use crate::simulator::{CellId, Simulator};
+use crate::{Scalar, Vec2};
+
+/// Time step of the environment, the same of the user interface.
+pub const DEFAULT_TIME_STEP: Scalar = 1.0 / 60.0;
+
+/// Reinforcement learning environment, like the ones of Gym, where an agent cell is
+/// controlled by the actions of a policy instead of its neuronal network.
+///
+/// The observations are the normalized inputs of the neurons of the agent,
+/// and the actions are the outputs, in the order of [`Neurons::input_fields`]
+/// and [`Neurons::output_fields`].
+pub struct Environment {
+    world_size: Vec2,
+    configure_simulator: Box<dyn Fn(Simulator) -> Simulator>,
+    simulator: Simulator,
+    agent: Option<CellId>,
+    /// Shared with the controller of the agent.
+    action: Rc<RefCell<Vec<Scalar>>>,
+    time_step: Scalar,
+    max_steps: Option<usize>,
+    steps: usize,
+}
+
+/// Result of a step of the environment.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    /// Observation after the step. It's all zeros when the agent is dead.
+    pub observation: Vec<Scalar>,
+    /// Energy gained by the agent during the step.
+    pub reward: Scalar,
+    /// The agent died.
+    pub done: bool,
+    /// The maximum number of steps was reached.
+    pub truncated: bool,
+}
+
+impl Environment {
+    /// Environment with a new simulator of the world size on every reset, configured by the function.
+    /// The simulator is seeded before configuring it, so the cells it adds are reproducible too.
+    pub fn new(
+        world_size: Vec2,
+        configure_simulator: impl Fn(Simulator) -> Simulator + 'static,
+    ) -> Self {
+        Self {
+            world_size,
+            simulator: configure_simulator(Simulator::new(world_size)),
+            configure_simulator: Box::new(configure_simulator),
+            agent: None,
+            action: Rc::new(RefCell::new(vec![0.0; Self::action_size()])),
+            time_step: DEFAULT_TIME_STEP,
+            max_steps: None,
+            steps: 0,
+        }
+    }
+
+    pub fn with_time_step(mut self, time_step: Scalar) -> Self {
+        self.time_step = time_step;
+        self
+    }
+
+    /// Maximum number of steps of an episode, after which the transitions are truncated.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn observation_size() -> usize {
+        Neurons::input_fields()
+            .last()
+            .map_or(0, |field| field.range().end)
+    }
+
+    pub fn action_size() -> usize {
+        Neurons::output_fields()
+            .last()
+            .map_or(0, |field| field.range().end)
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    pub fn simulator_mut(&mut self) -> &mut Simulator {
+        &mut self.simulator
+    }
+
+    pub fn agent(&self) -> Option<CellId> {
+        self.agent
+    }
+
+    /// Starts a new episode with a new simulator and a new random agent cell,
+    /// and returns the first observation. The seed makes the episode reproducible.
+    pub fn reset(&mut self, seed: Option<u64>) -> Vec<Scalar> {
+        let simulator = Simulator::new(self.world_size);
+        let simulator = match seed {
+            Some(seed) => simulator.with_seed(seed),
+            None => simulator,
+        };
+        self.simulator = (self.configure_simulator)(simulator);
+        self.steps = 0;
+        let agent = self.simulator.add_random_cell();
+        self.select_agent(agent);
+        self.action.borrow_mut().fill(0.0);
+        self.simulator.update(self.time_step);
+        self.observation()
+    }
+
+    /// Controls another cell of the simulator, releasing the previous agent.
+    /// Returns false if there is no such cell.
+    pub fn select_agent(&mut self, cell_id: CellId) -> bool {
+        let action = Rc::clone(&self.action);
+        let controller = move |neurons: &mut Neurons| {
+            let action = action.borrow();
+            for field in Neurons::output_fields() {
+                neurons.set_output(field.name, &action[field.range()]);
+            }
+        };
+        if !self.simulator.set_controller(cell_id, controller) {
+            return false;
+        }
+        if let Some(previous) = self.agent.replace(cell_id) {
+            if previous != cell_id {
+                self.simulator.remove_controller(previous);
+            }
+        }
+        true
+    }
+
+    /// Normalized inputs of the neurons of the agent on the last step.
+    pub fn observation(&self) -> Vec<Scalar> {
+        self.agent
+            .and_then(|agent| self.simulator.get_cell_view(agent))
+            .map(|cell| cell.neurons().normalized_inputs().as_slice().to_vec())
+            .unwrap_or_else(|| vec![0.0; Self::observation_size()])
+    }
+
+    /// Applies the action to the agent and updates the simulator.
+    /// The cells die without energy, so the reward when the agent dies is the loss of its energy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of the action isn't [`Environment::action_size`].
+    pub fn step(&mut self, action: &[Scalar]) -> Transition {
+        assert_eq!(action.len(), Self::action_size(), "invalid action size");
+        self.action.borrow_mut().copy_from_slice(action);
+
+        let energy = self.agent_energy();
+        self.simulator.update(self.time_step);
+        self.steps += 1;
+
+        let done = self.agent_energy().is_none();
+        if done {
+            self.agent = None;
+        }
+        Transition {
+            observation: self.observation(),
+            reward: self.agent_energy().unwrap_or_default() - energy.unwrap_or_default(),
+            done,
+            truncated: self
+                .max_steps
+                .is_some_and(|max_steps| self.steps >= max_steps),
+        }
+    }
+
+    fn agent_energy(&self) -> Option<Scalar> {
+        self.agent
+            .and_then(|agent| self.simulator.get_cell_view(agent))
+            .map(|cell| cell.energy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment() -> Environment {
+        Environment::new(Vec2::new(200.0, 200.0), |mut simulator| {
+            for _ in 0..5 {
+                simulator.add_random_cell();
+            }
+            simulator
+        })
+    }
+
+    #[test]
+    fn resets_with_the_same_seed_are_reproducible() {
+        let mut environment1 = environment();
+        let mut environment2 = environment();
+        let action = vec![0.5; Environment::action_size()];
+
+        for seed in [1, 2] {
+            assert_eq!(
+                environment1.reset(Some(seed)),
+                environment2.reset(Some(seed))
+            );
+            for _ in 0..10 {
+                let transition1 = environment1.step(&action);
+                let transition2 = environment2.step(&action);
+                assert_eq!(transition1.observation, transition2.observation);
+                assert_eq!(transition1.reward, transition2.reward);
+            }
+            let positions = |environment: &Environment| {
+                let simulator = environment.simulator();
+                simulator
+                    .cells()
+                    .filter_map(|(cell_id, _)| simulator.get_cell_view(cell_id))
+                    .map(|cell| cell.position())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(positions(&environment1), positions(&environment2));
+        }
+    }
+}
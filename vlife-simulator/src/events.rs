@@ -0,0 +1,57 @@
+use crate::simulator::CellId;
+use crate::Scalar;
+
+/// Something relevant that happened to the population during a simulation step.
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Simulation time when it happened.
+    pub time: Scalar,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    /// A new cell was born from the mating of two cells.
+    Born {
+        cell_id: CellId,
+        parent1_id: CellId,
+        parent2_id: CellId,
+    },
+    /// A cell divided, and a new child cell was born.
+    Divided { cell_id: CellId, child_id: CellId },
+    /// A cell died.
+    Died { cell_id: CellId, cause: DeathCause },
+    /// A cell was added from outside the simulation, like from the user interface.
+    /// The parent is the cell it was cloned from, if any.
+    Added {
+        cell_id: CellId,
+        parent_id: Option<CellId>,
+    },
+    /// A new cell was added to keep the minimum number of cells.
+    Reseeded {
+        cell_id: CellId,
+        source: ReseedSource,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeathCause {
+    /// The cell run out of energy.
+    Starvation,
+    /// The cell run out of energy while other cells were absorbing it.
+    Predation,
+    /// The cell stayed without energy longer than its `zero_energy_limit`.
+    ZeroEnergyTimeout,
+    /// The cell was removed from the simulation, like from the user interface.
+    Removed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReseedSource {
+    /// The genome was recombined from the best dead cells of the rank.
+    Rank,
+    /// The genome of one of the best dead cells of the rank was reinserted unchanged.
+    Elite,
+    /// The cell was created randomly.
+    Random,
+}
@@ -0,0 +1,80 @@
+use crate::cell::Cell;
+use crate::Scalar;
+
+/// Scores a dead cell before inserting it into the rank.
+/// Higher scores make the genome of the cell more likely to be selected.
+pub trait FitnessFunction {
+    fn score(&self, cell: &Cell) -> Scalar;
+}
+
+impl<F: Fn(&Cell) -> Scalar> FitnessFunction for F {
+    fn score(&self, cell: &Cell) -> Scalar {
+        self(cell)
+    }
+}
+
+/// Ratio between the energy gained and the energy lost during the cell life.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnergyRatioFitness;
+
+impl FitnessFunction for EnergyRatioFitness {
+    fn score(&self, cell: &Cell) -> Scalar {
+        cell.stats().energy_ratio()
+    }
+}
+
+/// Time the cell was alive.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LifespanFitness;
+
+impl FitnessFunction for LifespanFitness {
+    fn score(&self, cell: &Cell) -> Scalar {
+        cell.age()
+    }
+}
+
+/// Number of cells born from the cell, either by division or mating.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OffspringFitness;
+
+impl FitnessFunction for OffspringFitness {
+    fn score(&self, cell: &Cell) -> Scalar {
+        cell.stats().offspring as Scalar
+    }
+}
+
+/// Distance travelled by the cell during its life.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DistanceFitness;
+
+impl FitnessFunction for DistanceFitness {
+    fn score(&self, cell: &Cell) -> Scalar {
+        cell.stats().distance_travelled
+    }
+}
+
+/// Weighted sum of other fitness functions.
+#[derive(Default)]
+pub struct WeightedFitness {
+    functions: Vec<(Scalar, Box<dyn FitnessFunction>)>,
+}
+
+impl WeightedFitness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, weight: Scalar, function: impl FitnessFunction + 'static) -> Self {
+        self.functions.push((weight, Box::new(function)));
+        self
+    }
+}
+
+impl FitnessFunction for WeightedFitness {
+    fn score(&self, cell: &Cell) -> Scalar {
+        self.functions
+            .iter()
+            .map(|(weight, function)| weight * function.score(cell))
+            .sum()
+    }
+}
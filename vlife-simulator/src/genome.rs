@@ -1,15 +1,35 @@
-use rand::Rng;
-use std::collections::BTreeSet;
-use std::{cell::RefCell, collections::BTreeMap};
+use rand::{seq::index::sample, Rng};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
 
 use crate::{Scalar, M};
 
 pub trait BuildGenome {
-    fn build_genome<'a>(&self, builder: GenomeBuilder);
+    fn build_genome(&self, builder: GenomeBuilder);
 }
 
 pub trait ApplyGenome {
-    fn apply_genome(&mut self, genome: &Genome);
+    fn apply_genome(&mut self, reader: GenomeReader<'_>);
+}
+
+/// How the genes of two genomes are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CrossoverOperator {
+    /// Genes before a random point of the sorted keys come from the first genome,
+    /// and the rest from the second one.
+    #[default]
+    SinglePoint,
+    /// Like the single point, but alternating the genomes at several random points.
+    MultiPoint { points: usize },
+    /// Every gen comes from any of the genomes with the same probability.
+    Uniform,
+    /// Every gen is a random blend of the values from both genomes (BLX-α).
+    /// Discrete genes, like flags, categories or node ids, come from any of the genomes instead.
+    Blend { alpha: Scalar },
+    /// Whole subtrees of genes, like `neurons/input_layer`, come from any of the genomes.
+    /// The `depth` is the number of path components identifying a subtree.
+    Subtree { depth: usize },
 }
 
 #[derive(Debug, Clone)]
@@ -18,51 +38,239 @@ pub struct Genome {
 }
 
 impl Genome {
-    pub fn _get(&self, path: Option<String>, name: &str) -> Option<&Gen> {
-        let id = Self::gen_id(path.as_deref(), name);
+    pub fn get(&self, path: Option<&str>, name: &str) -> Option<&Gen> {
+        let id = Self::gen_id(path, name);
         self.genes.get(&id)
     }
 
+    pub fn reader(&self) -> GenomeReader<'_> {
+        GenomeReader {
+            path: None,
+            genome: self,
+        }
+    }
+
+    /// Full paths of all the genes with their values, sorted by path.
+    pub fn genes(&self) -> impl Iterator<Item = (&str, Scalar)> + '_ {
+        self.genes
+            .iter()
+            .map(|(key, gen)| (key.as_str(), gen.value))
+    }
+
+    /// Changes the value of an existing gen. Returns false if there is no gen with the full path.
+    pub fn set(&mut self, key: &str, value: Scalar) -> bool {
+        match self.genes.get_mut(key) {
+            Some(gen) => {
+                gen.value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Names of the genes and nested paths directly under the path.
+    pub fn children(&self, path: Option<&str>) -> Vec<String> {
+        let prefix = path.map(|path| format!("{path}/")).unwrap_or_default();
+        let mut children: Vec<String> = Vec::new();
+        for key in self
+            .genes
+            .range(prefix.clone()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix))
+        {
+            let child = key[prefix.len()..].split('/').next().unwrap_or_default();
+            // Keys are sorted, so the repeated children are always consecutive
+            if children.last().is_none_or(|last| last != child) {
+                children.push(child.to_string());
+            }
+        }
+        children
+    }
+
     pub(crate) fn _mutate(&mut self, _num_mutations: usize, _probability: Scalar) {
         todo!()
     }
 
-    pub(crate) fn cross(&self, other: &Genome) -> Genome {
-        let mut rng = rand::thread_rng();
-        let keys1 = self
-            .genes
-            .keys()
-            .map(String::as_str)
-            .collect::<BTreeSet<_>>();
-        let keys2 = other
+    pub(crate) fn cross<R: Rng>(
+        &self,
+        rng: &mut R,
+        other: &Genome,
+        operator: CrossoverOperator,
+    ) -> Genome {
+        let keys = self
             .genes
             .keys()
+            .chain(other.genes.keys())
             .map(String::as_str)
-            .collect::<BTreeSet<_>>();
-        let keys = keys1.union(&keys2).collect::<Vec<_>>();
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
         let num_genes = keys.len();
-        let cross_index = rng.gen_range(1..num_genes - 1);
+        match operator {
+            CrossoverOperator::SinglePoint => {
+                let cross_index = if num_genes > 1 {
+                    rng.gen_range(1..num_genes)
+                } else {
+                    num_genes
+                };
+                self.cross_by(other, &keys, |index, _| index >= cross_index)
+            }
+            CrossoverOperator::MultiPoint { points } => {
+                let mut cross_indices = if num_genes > 1 {
+                    let amount = points.min(num_genes - 1);
+                    sample(rng, num_genes - 1, amount)
+                        .into_iter()
+                        .map(|index| index + 1)
+                        .collect::<Vec<_>>()
+                } else {
+                    Vec::new()
+                };
+                cross_indices.sort_unstable();
+                self.cross_by(other, &keys, |index, _| {
+                    cross_indices.partition_point(|cross_index| *cross_index <= index) % 2 == 1
+                })
+            }
+            CrossoverOperator::Uniform => self.cross_by(other, &keys, |_, _| rng.gen_bool(0.5)),
+            CrossoverOperator::Blend { alpha } => self.blend(rng, other, &keys, alpha),
+            CrossoverOperator::Subtree { depth } => {
+                let mut subtrees = HashMap::new();
+                self.cross_by(other, &keys, |_, key| {
+                    let subtree = key.split('/').take(depth.max(1)).collect::<Vec<_>>();
+                    *subtrees
+                        .entry(subtree.join("/"))
+                        .or_insert_with(|| rng.gen_bool(0.5))
+                })
+            }
+        }
+    }
+
+    /// Takes every gen from the other genome when `from_other` returns true, or from this one otherwise.
+    /// Genes that only exist in one of the genomes are always taken from it.
+    fn cross_by(
+        &self,
+        other: &Genome,
+        keys: &[&str],
+        mut from_other: impl FnMut(usize, &str) -> bool,
+    ) -> Genome {
         let mut genes = BTreeMap::new();
-        let (keys1, keys2) = keys.split_at(cross_index);
-        for key in keys1 {
-            let gen = self
+        for (index, key) in keys.iter().enumerate() {
+            let (first, second) = if from_other(index, key) {
+                (other, self)
+            } else {
+                (self, other)
+            };
+            let gen = first
                 .genes
-                .get(**key)
-                .or_else(|| other.genes.get(**key))
+                .get(*key)
+                .or_else(|| second.genes.get(*key))
                 .expect("gen");
             genes.insert(key.to_string(), gen.clone());
         }
-        for key in keys2 {
-            let gen = other
-                .genes
-                .get(**key)
-                .or_else(|| self.genes.get(**key))
-                .expect("gen");
-            genes.insert(key.to_string(), gen.clone());
+        Genome { genes }
+    }
+
+    /// BLX-α crossover. Values are chosen uniformly from the range between both parents,
+    /// extended by `alpha` times its length on both sides. Values don't change sign when
+    /// both parents agree, as many genes are only meaningful when positive.
+    /// Discrete genes aren't blended, but taken from any of the parents.
+    fn blend<R: Rng>(&self, rng: &mut R, other: &Genome, keys: &[&str], alpha: Scalar) -> Genome {
+        let mut genes = BTreeMap::new();
+        for key in keys {
+            let gen = match (self.genes.get(*key), other.genes.get(*key)) {
+                (Some(gen1), Some(gen2)) if gen1.discrete || gen2.discrete => {
+                    if rng.gen_bool(0.5) {
+                        gen1.clone()
+                    } else {
+                        gen2.clone()
+                    }
+                }
+                (Some(gen1), Some(gen2)) => {
+                    let min = gen1.value.min(gen2.value);
+                    let max = gen1.value.max(gen2.value);
+                    let extension = alpha * (max - min);
+                    let mut value = if extension > 0.0 {
+                        rng.gen_range(min - extension..=max + extension)
+                    } else {
+                        min
+                    };
+                    if min >= 0.0 {
+                        value = value.max(0.0);
+                    } else if max <= 0.0 {
+                        value = value.min(0.0);
+                    }
+                    Gen::new(value)
+                }
+                (Some(gen), None) | (None, Some(gen)) => gen.clone(),
+                (None, None) => continue,
+            };
+            genes.insert(key.to_string(), gen);
         }
         Genome { genes }
     }
 
+    /// Compatibility distance between two genomes, similar to the one used by NEAT.
+    /// It adds the proportion of genes that only exist in one of the genomes,
+    /// and the mean difference of the matching genes, relative to their magnitude.
+    pub fn distance(&self, other: &Genome) -> Scalar {
+        Self::sorted_distance(self.genes(), other.genes())
+    }
+
+    /// Same as [`Genome::distance`], for any two lists of genes sorted by key.
+    pub(crate) fn sorted_distance<'a, 'b>(
+        genes1: impl Iterator<Item = (&'a str, Scalar)>,
+        genes2: impl Iterator<Item = (&'b str, Scalar)>,
+    ) -> Scalar {
+        let mut disjoint = 0usize;
+        let mut matching = 0usize;
+        let mut difference = 0.0;
+        // Both lists are sorted by key, so they can be merged in a single pass
+        let mut genes1 = genes1.peekable();
+        let mut genes2 = genes2.peekable();
+        loop {
+            match (genes1.peek(), genes2.peek()) {
+                (Some((key1, value1)), Some((key2, value2))) => match key1.cmp(key2) {
+                    Ordering::Less => {
+                        disjoint += 1;
+                        genes1.next();
+                    }
+                    Ordering::Greater => {
+                        disjoint += 1;
+                        genes2.next();
+                    }
+                    Ordering::Equal => {
+                        matching += 1;
+                        difference += Self::gene_difference(*value1, *value2);
+                        genes1.next();
+                        genes2.next();
+                    }
+                },
+                (Some(_), None) | (None, Some(_)) => {
+                    disjoint += genes1.count() + genes2.count();
+                    break;
+                }
+                (None, None) => break,
+            }
+        }
+        Self::combine_distance(disjoint, matching, difference)
+    }
+
+    /// Difference between the values of a matching gen, relative to their magnitude.
+    pub(crate) fn gene_difference(value1: Scalar, value2: Scalar) -> Scalar {
+        let magnitude = value1.abs().max(value2.abs()).max(1.0);
+        (value1 - value2).abs() / magnitude
+    }
+
+    /// Distance given the number of disjoint and matching genes, and the sum of the differences.
+    pub(crate) fn combine_distance(disjoint: usize, matching: usize, difference: Scalar) -> Scalar {
+        let num_genes = (matching + disjoint).max(1) as Scalar;
+        let mean_difference = if matching > 0 {
+            difference / matching as Scalar
+        } else {
+            0.0
+        };
+        disjoint as Scalar / num_genes + mean_difference
+    }
+
     fn gen_id(path: Option<&str>, name: &str) -> String {
         if let Some(path) = path {
             format!("{path}/{name}")
@@ -74,22 +282,57 @@ impl Genome {
 
 #[derive(Debug, Clone)]
 pub struct Gen {
-    // This is used by the GenomeBuilder macro
-    #[allow(dead_code)]
     pub(crate) value: Scalar,
+    /// Whether the value is a flag, a category or an id, so it can't be blended.
+    pub(crate) discrete: bool,
+}
+
+impl Gen {
+    pub fn new(value: Scalar) -> Self {
+        Self {
+            value,
+            discrete: false,
+        }
+    }
+
+    pub fn discrete(value: Scalar) -> Self {
+        Self {
+            value,
+            discrete: true,
+        }
+    }
+
+    pub fn is_discrete(&self) -> bool {
+        self.discrete
+    }
+
+    pub fn value(&self) -> Scalar {
+        self.value
+    }
 }
 
 #[derive(Clone)]
 pub struct GenomeBuilder {
     path: Option<String>,
-    genes: RefCell<BTreeMap<String, Gen>>,
+    /// Whether all the genes added are discrete.
+    discrete: bool,
+    genes: Rc<RefCell<BTreeMap<String, Gen>>>,
 }
 
 impl GenomeBuilder {
     pub fn new() -> Self {
         Self {
             path: None,
-            genes: RefCell::new(BTreeMap::new()),
+            discrete: false,
+            genes: Rc::new(RefCell::new(BTreeMap::new())),
+        }
+    }
+
+    /// Same builder, but marking all the genes added as discrete, like for flags or categories.
+    pub fn discrete(&self) -> Self {
+        Self {
+            discrete: true,
+            ..self.clone()
         }
     }
 
@@ -102,40 +345,220 @@ impl GenomeBuilder {
 
         Self {
             path,
+            discrete: self.discrete,
             genes: self.genes.clone(),
         }
     }
 
-    pub fn add(&self, name: &str, gen: Gen) {
+    pub fn add(&self, name: &str, mut gen: Gen) {
+        gen.discrete |= self.discrete;
         let id = Genome::gen_id(self.path.as_deref(), name);
         self.genes.borrow_mut().insert(id, gen);
     }
 
     pub fn build(self) -> Genome {
         Genome {
-            genes: self.genes.into_inner(),
+            genes: self.genes.take(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GenomeReader<'a> {
+    path: Option<String>,
+    genome: &'a Genome,
+}
+
+impl<'a> GenomeReader<'a> {
+    pub fn nested(&self, name: &str) -> Self {
+        let path = self
+            .path
+            .as_ref()
+            .map(|path| format!("{path}/{name}"))
+            .or_else(|| Some(name.to_string()));
+
+        Self {
+            path,
+            genome: self.genome,
         }
     }
+
+    pub fn get(&self, name: &str) -> Option<Scalar> {
+        self.genome
+            .get(self.path.as_deref(), name)
+            .map(|gen| gen.value)
+    }
+
+    /// Names of the genes and nested paths directly under the current path.
+    pub fn children(&self) -> Vec<String> {
+        self.genome.children(self.path.as_deref())
+    }
+
+    /// Whether there is any gen under the nested path.
+    pub fn contains_nested(&self, name: &str) -> bool {
+        !self.nested(name).children().is_empty()
+    }
 }
 
 // impl<const R: usize> BuildGenome for V<R> {
-//     fn build_genome<'a>(&self, builder: GenomeBuilder) {
+//     fn build_genome(&self, builder: GenomeBuilder) {
 //         for (index, value) in self.iter().copied().enumerate() {
 //             let name = format!("{index:03}");
-//             builder.add(&name, Gen { value });
+//             builder.add(&name, Gen::new(value));
 //         }
 //     }
 // }
 
 impl<const R: usize, const C: usize> BuildGenome for M<R, C> {
-    fn build_genome<'a>(&self, builder: GenomeBuilder) {
+    fn build_genome(&self, builder: GenomeBuilder) {
         for (row_index, row) in self.row_iter().enumerate() {
             let row_name = format!("{row_index:03}");
             let row_builder = builder.nested(&row_name);
             for (col_index, value) in row.iter().copied().enumerate() {
                 let col_name = format!("{col_index:03}");
-                row_builder.add(&col_name, Gen { value: value })
+                row_builder.add(&col_name, Gen::new(value))
             }
         }
     }
 }
+
+impl<const R: usize, const C: usize> ApplyGenome for M<R, C> {
+    fn apply_genome(&mut self, reader: GenomeReader<'_>) {
+        for (row_index, mut row) in self.row_iter_mut().enumerate() {
+            let row_name = format!("{row_index:03}");
+            let row_reader = reader.nested(&row_name);
+            for (col_index, value) in row.iter_mut().enumerate() {
+                let col_name = format!("{col_index:03}");
+                if let Some(gen_value) = row_reader.get(&col_name) {
+                    *value = gen_value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const OPERATORS: [CrossoverOperator; 5] = [
+        CrossoverOperator::SinglePoint,
+        CrossoverOperator::MultiPoint { points: 2 },
+        CrossoverOperator::Uniform,
+        CrossoverOperator::Blend { alpha: 0.5 },
+        CrossoverOperator::Subtree { depth: 1 },
+    ];
+
+    fn genome(genes: &[(&str, Gen)]) -> Genome {
+        let builder = GenomeBuilder::new();
+        for (name, gen) in genes {
+            builder.add(name, gen.clone());
+        }
+        builder.build()
+    }
+
+    fn values(genome: &Genome) -> Vec<(&str, Scalar)> {
+        genome.genes().collect()
+    }
+
+    #[test]
+    fn crossover_without_genes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for operator in OPERATORS {
+            let child = genome(&[]).cross(&mut rng, &genome(&[]), operator);
+            assert!(values(&child).is_empty());
+        }
+    }
+
+    #[test]
+    fn crossover_with_one_gene() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let genome1 = genome(&[("a", Gen::new(1.0))]);
+        let genome2 = genome(&[("a", Gen::new(2.0))]);
+        for operator in OPERATORS {
+            for _ in 0..100 {
+                let child = genome1.cross(&mut rng, &genome2, operator);
+                let [("a", value)] = values(&child)[..] else {
+                    panic!("unexpected genes {:?}", values(&child));
+                };
+                match operator {
+                    CrossoverOperator::Blend { alpha } => {
+                        assert!((1.0 - alpha..=2.0 + alpha).contains(&value))
+                    }
+                    _ => assert!(value == 1.0 || value == 2.0),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crossover_with_two_genes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let genome1 = genome(&[("a", Gen::new(1.0)), ("b", Gen::new(3.0))]);
+        let genome2 = genome(&[("a", Gen::new(2.0)), ("b", Gen::new(4.0))]);
+        for operator in OPERATORS {
+            for _ in 0..100 {
+                let child = genome1.cross(&mut rng, &genome2, operator);
+                let [("a", a), ("b", b)] = values(&child)[..] else {
+                    panic!("unexpected genes {:?}", values(&child));
+                };
+                match operator {
+                    // The only crossing point is between both genes
+                    CrossoverOperator::SinglePoint | CrossoverOperator::MultiPoint { .. } => {
+                        assert_eq!((a, b), (1.0, 4.0))
+                    }
+                    CrossoverOperator::Blend { .. } => {
+                        assert!((0.5..=2.5).contains(&a) && (2.5..=4.5).contains(&b))
+                    }
+                    _ => assert!((a == 1.0 || a == 2.0) && (b == 3.0 || b == 4.0)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crossover_keeps_the_genes_of_a_single_parent() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let genome1 = genome(&[("a", Gen::new(1.0))]);
+        let genome2 = genome(&[("b", Gen::new(2.0))]);
+        for operator in OPERATORS {
+            let child = genome1.cross(&mut rng, &genome2, operator);
+            assert_eq!(values(&child), vec![("a", 1.0), ("b", 2.0)]);
+        }
+    }
+
+    #[test]
+    fn blend_takes_discrete_genes_from_a_parent() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let genome1 = genome(&[("enabled", Gen::discrete(0.0)), ("to", Gen::discrete(7.0))]);
+        let genome2 = genome(&[("enabled", Gen::discrete(1.0)), ("to", Gen::discrete(9.0))]);
+        for _ in 0..100 {
+            let child = genome1.cross(&mut rng, &genome2, CrossoverOperator::Blend { alpha: 0.5 });
+            let enabled = child.get(None, "enabled").unwrap();
+            let to = child.get(None, "to").unwrap();
+            assert!(enabled.is_discrete() && to.is_discrete());
+            assert!(enabled.value() == 0.0 || enabled.value() == 1.0);
+            assert!(to.value() == 7.0 || to.value() == 9.0);
+        }
+    }
+
+    #[test]
+    fn discrete_builders_mark_nested_genes() {
+        let builder = GenomeBuilder::new();
+        builder.add("weight", Gen::new(0.5));
+        builder
+            .nested("enabled")
+            .discrete()
+            .nested("000")
+            .add("001", Gen::new(1.0));
+        let genome = builder.build();
+
+        assert!(!genome.get(None, "weight").unwrap().is_discrete());
+        assert!(genome
+            .get(Some("enabled/000"), "001")
+            .unwrap()
+            .is_discrete());
+    }
+}
@@ -1,13 +1,44 @@
 pub mod cell;
 mod cell_rank;
+mod controller;
+mod environment;
+mod events;
+mod fitness;
 mod genome;
+mod lineage;
+mod neat;
+mod network_graph;
 mod neurons;
 mod physics;
+mod selection;
 mod simulator;
+mod species;
+mod statistics;
 
 use nalgebra::{Const, MatrixView, SMatrix, SVector, Vector2};
 
-pub use simulator::{CellId, Cells, Simulator};
+pub use cell_rank::{CellRank, RankAging, RankEntry};
+pub use controller::Controller;
+pub use environment::{Environment, Transition, DEFAULT_TIME_STEP};
+pub use events::{DeathCause, Event, EventKind, ReseedSource};
+pub use fitness::{
+    DistanceFitness, EnergyRatioFitness, FitnessFunction, LifespanFitness, OffspringFitness,
+    WeightedFitness,
+};
+pub use genome::{CrossoverOperator, Gen, Genome};
+pub use lineage::{Lineage, LineageId, Phylogeny, PhylogenyNode};
+pub use neat::{
+    ConnectionGene, InnovationId, Innovations, NeatMutation, NeatNetwork, NodeGene, NodeId,
+};
+pub use network_graph::{GraphEdge, GraphNode, GraphNodeKind, NetworkGraph};
+pub use neurons::{
+    ActivationFunction, BrainType, InputField, Neurons, NeuronsField, Normalization,
+    PruningMutation,
+};
+pub use selection::SelectionStrategy;
+pub use simulator::{CellId, Cells, Simulator, MAX_EVENTS};
+pub use species::{Speciation, Species, SpeciesId};
+pub use statistics::{Distribution, Sample, Statistics, MAX_SAMPLES};
 
 pub type Scalar = f64;
 pub type Vec2 = Vector2<Scalar>;
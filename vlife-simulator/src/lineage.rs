@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::simulator::CellId;
+use crate::Scalar;
+
+/// The lineage is identified by the id of the cell that founded it.
+pub type LineageId = CellId;
+
+/// Minimum number of nodes of the phylogeny before pruning it.
+pub const MIN_PRUNE_SIZE: usize = 1000;
+
+/// Ancestry of a cell.
+#[derive(Debug, Clone, Default)]
+pub struct Lineage {
+    /// Id of the cell. This is assigned when the cell is added to the simulation.
+    pub cell_id: CellId,
+    /// Parents of the cell. Empty for random cells, one for divisions, and two for matings.
+    pub parents: Vec<CellId>,
+    /// Number of generations since the founder of the lineage.
+    pub generation: usize,
+    /// The lineage is inherited from the first parent, or started by cells without parents.
+    pub lineage_id: LineageId,
+}
+
+impl Lineage {
+    pub fn new(cell_id: CellId, parents: &[Lineage]) -> Self {
+        Self {
+            cell_id,
+            parents: parents.iter().map(|parent| parent.cell_id).collect(),
+            generation: parents
+                .iter()
+                .map(|parent| parent.generation + 1)
+                .max()
+                .unwrap_or(0),
+            lineage_id: parents.first().map_or(cell_id, |parent| parent.lineage_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PhylogenyNode {
+    pub lineage: Lineage,
+    /// Simulation time when the cell was born.
+    pub born: Scalar,
+    /// Simulation time when the cell died, if it already did.
+    pub died: Option<Scalar>,
+}
+
+impl PhylogenyNode {
+    pub fn is_alive(&self) -> bool {
+        self.died.is_none()
+    }
+}
+
+/// Phylogenetic tree of the cells added to the simulation.
+///
+/// The tree is pruned every time its size doubles, so it only keeps the ancestors of the living cells.
+/// Dead ancestors with a single child are also removed, and the parent of the child becomes the
+/// closest remaining ancestor, so the tree stays compact while keeping the branch lengths.
+#[derive(Debug, Clone, Default)]
+pub struct Phylogeny {
+    nodes: BTreeMap<CellId, PhylogenyNode>,
+    /// Size of the tree after the last pruning.
+    pruned_size: usize,
+}
+
+impl Phylogeny {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, cell_id: CellId) -> Option<&PhylogenyNode> {
+        self.nodes.get(&cell_id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &PhylogenyNode> + '_ {
+        self.nodes.values()
+    }
+
+    pub(crate) fn add(&mut self, lineage: &Lineage, time: Scalar) {
+        let node = PhylogenyNode {
+            lineage: lineage.clone(),
+            born: time,
+            died: None,
+        };
+        self.nodes.insert(lineage.cell_id, node);
+        if self.nodes.len() >= (2 * self.pruned_size).max(MIN_PRUNE_SIZE) {
+            self.prune_extinct();
+        }
+    }
+
+    pub(crate) fn set_died(&mut self, cell_id: CellId, time: Scalar) {
+        if let Some(node) = self.nodes.get_mut(&cell_id) {
+            node.died = Some(time);
+        }
+    }
+
+    /// Number of living cells for every lineage.
+    pub fn living_lineages(&self) -> BTreeMap<LineageId, usize> {
+        let mut lineages = BTreeMap::new();
+        for node in self.nodes.values().filter(|node| node.is_alive()) {
+            *lineages.entry(node.lineage.lineage_id).or_default() += 1;
+        }
+        lineages
+    }
+
+    /// Removes the dead cells that don't have any living descendant,
+    /// and coalesces the dead cells with a single child.
+    pub fn prune_extinct(&mut self) {
+        // Cells are always born after their parents, so their ids are greater.
+        let mut living = BTreeSet::new();
+        for (cell_id, node) in self.nodes.iter().rev() {
+            if node.is_alive() || living.contains(cell_id) {
+                living.insert(*cell_id);
+                living.extend(node.lineage.parents.iter().copied());
+            }
+        }
+        self.nodes.retain(|cell_id, _| living.contains(cell_id));
+        self.coalesce();
+        self.pruned_size = self.nodes.len();
+    }
+
+    /// Removes the dead cells that are the first parent of their only child, except the roots.
+    fn coalesce(&mut self) {
+        let mut children = BTreeMap::<CellId, Vec<CellId>>::new();
+        for (cell_id, node) in self.nodes.iter() {
+            for parent_id in node.lineage.parents.iter() {
+                if self.nodes.contains_key(parent_id) {
+                    children.entry(*parent_id).or_default().push(*cell_id);
+                }
+            }
+        }
+
+        // Parents are visited before their children, so whole chains are coalesced into their top
+        let cell_ids: Vec<_> = self.nodes.keys().copied().collect();
+        for cell_id in cell_ids {
+            let Some(node) = self.nodes.get(&cell_id).filter(|node| !node.is_alive()) else {
+                continue;
+            };
+            let Some(grandparent_id) = node
+                .lineage
+                .parents
+                .first()
+                .copied()
+                .filter(|parent_id| self.nodes.contains_key(parent_id))
+            else {
+                continue;
+            };
+            let Some(&[child_id]) = children.get(&cell_id).map(Vec::as_slice) else {
+                continue;
+            };
+            let Some(child) = self
+                .nodes
+                .get_mut(&child_id)
+                .filter(|child| child.lineage.parents.first() == Some(&cell_id))
+            else {
+                continue;
+            };
+
+            child.lineage.parents[0] = grandparent_id;
+            self.nodes.remove(&cell_id);
+            if let Some(siblings) = children.get_mut(&grandparent_id) {
+                for sibling_id in siblings.iter_mut().filter(|id| **id == cell_id) {
+                    *sibling_id = child_id;
+                }
+            }
+        }
+    }
+
+    /// Exports the tree in Newick format.
+    /// Only the first parent is used to build the tree, and branch lengths are the
+    /// simulation time between the birth of the parent and the birth of the child.
+    pub fn to_newick(&self) -> String {
+        let mut children = BTreeMap::<CellId, Vec<CellId>>::new();
+        let mut roots = Vec::new();
+        for (cell_id, node) in self.nodes.iter() {
+            match node
+                .lineage
+                .parents
+                .first()
+                .filter(|parent_id| self.nodes.contains_key(parent_id))
+            {
+                Some(parent_id) => children.entry(*parent_id).or_default().push(*cell_id),
+                None => roots.push(*cell_id),
+            }
+        }
+
+        let mut newick = String::new();
+        let mut stack = Vec::new();
+        NewickStep::push_children(&mut stack, &roots);
+        if roots.len() > 1 {
+            newick.push('(');
+        }
+        while let Some(step) = stack.pop() {
+            match step {
+                NewickStep::Open(cell_id) => match children.get(&cell_id) {
+                    Some(cell_children) => {
+                        newick.push('(');
+                        stack.push(NewickStep::Close(cell_id));
+                        NewickStep::push_children(&mut stack, cell_children);
+                    }
+                    None => self.write_newick_label(&mut newick, cell_id),
+                },
+                NewickStep::Close(cell_id) => {
+                    newick.push(')');
+                    self.write_newick_label(&mut newick, cell_id);
+                }
+                NewickStep::Separator => newick.push(','),
+            }
+        }
+        if roots.len() > 1 {
+            newick.push(')');
+        }
+        newick.push(';');
+        newick
+    }
+
+    fn write_newick_label(&self, newick: &mut String, cell_id: CellId) {
+        let _ = write!(newick, "{cell_id}");
+        if let Some(node) = self.nodes.get(&cell_id) {
+            let parent = node
+                .lineage
+                .parents
+                .first()
+                .and_then(|parent_id| self.nodes.get(parent_id));
+            if let Some(parent) = parent {
+                let _ = write!(newick, ":{:.3}", node.born - parent.born);
+            }
+        }
+    }
+}
+
+/// Steps to write the Newick tree without recursion, as lineages can be very deep.
+enum NewickStep {
+    Open(CellId),
+    Close(CellId),
+    Separator,
+}
+
+impl NewickStep {
+    /// Pushes the children in reverse order, so they are popped in the right one.
+    fn push_children(stack: &mut Vec<NewickStep>, children: &[CellId]) {
+        for (index, cell_id) in children.iter().rev().enumerate() {
+            if index > 0 {
+                stack.push(NewickStep::Separator);
+            }
+            stack.push(NewickStep::Open(*cell_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lineage(cell_id: CellId, parent: Option<&Lineage>) -> Lineage {
+        Lineage::new(
+            cell_id,
+            parent.map(std::slice::from_ref).unwrap_or_default(),
+        )
+    }
+
+    #[test]
+    fn newick_of_a_small_tree() {
+        let mut phylogeny = Phylogeny::new();
+        let root = lineage(0, None);
+        let child1 = lineage(1, Some(&root));
+        let child2 = lineage(2, Some(&root));
+        let grandchild = lineage(3, Some(&child1));
+        phylogeny.add(&root, 0.0);
+        phylogeny.add(&child1, 1.0);
+        phylogeny.add(&child2, 2.0);
+        phylogeny.add(&grandchild, 4.0);
+
+        assert_eq!(phylogeny.to_newick(), "((3:3.000)1:1.000,2:2.000)0;");
+    }
+
+    #[test]
+    fn newick_of_several_roots() {
+        let mut phylogeny = Phylogeny::new();
+        phylogeny.add(&lineage(0, None), 0.0);
+        phylogeny.add(&lineage(1, None), 1.0);
+
+        assert_eq!(phylogeny.to_newick(), "(0,1);");
+    }
+
+    #[test]
+    fn pruning_removes_extinct_branches_and_coalesces_chains() {
+        let mut phylogeny = Phylogeny::new();
+        let root = lineage(0, None);
+        let extinct = lineage(1, Some(&root));
+        let middle = lineage(2, Some(&root));
+        let leaf = lineage(3, Some(&middle));
+        phylogeny.add(&root, 0.0);
+        phylogeny.add(&extinct, 1.0);
+        phylogeny.add(&middle, 2.0);
+        phylogeny.add(&leaf, 5.0);
+        phylogeny.set_died(0, 3.0);
+        phylogeny.set_died(1, 3.0);
+        phylogeny.set_died(2, 6.0);
+
+        phylogeny.prune_extinct();
+
+        assert_eq!(phylogeny.len(), 2);
+        assert_eq!(phylogeny.get(3).unwrap().lineage.parents, vec![0]);
+        assert_eq!(phylogeny.to_newick(), "(3:5.000)0;");
+    }
+
+    #[test]
+    fn tree_stays_bounded() {
+        let mut phylogeny = Phylogeny::new();
+        let mut living = vec![lineage(0, None), lineage(1, None)];
+        for lineage in living.iter() {
+            phylogeny.add(lineage, 0.0);
+        }
+        for cell_id in 2..20_000 {
+            // Every new cell replaces the oldest living one
+            let time = cell_id as Scalar;
+            let parent = living.remove(0);
+            let child = lineage(cell_id, Some(&parent));
+            phylogeny.set_died(parent.cell_id, time);
+            phylogeny.add(&child, time);
+            living.push(child);
+            assert!(phylogeny.len() <= 2 * MIN_PRUNE_SIZE);
+        }
+
+        phylogeny.prune_extinct();
+        assert_eq!(phylogeny.len(), 4);
+        assert_eq!(phylogeny.living_lineages().len(), 2);
+    }
+}
@@ -0,0 +1,720 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use rand::seq::{index::sample, IteratorRandom, SliceRandom};
+use rand::Rng;
+
+use crate::genome::{ApplyGenome, BuildGenome, Gen, GenomeBuilder, GenomeReader};
+use crate::neurons::{ActivationFunction, PruningMutation};
+use crate::{Scalar, V};
+
+pub type NodeId = usize;
+pub type InnovationId = usize;
+
+/// Number of random connections from the inputs to every output in the initial networks.
+const INITIAL_CONNECTIONS_PER_OUTPUT: usize = 2;
+/// Attempts to find two unconnected nodes when adding a connection.
+const ADD_CONNECTION_ATTEMPTS: usize = 20;
+
+/// Registry of the structural mutations.
+/// The same mutation always gets the same innovation number, even in unrelated cells,
+/// so the genes of different networks can be aligned by the crossover.
+#[derive(Debug, Clone, Default)]
+pub struct Innovations {
+    connections: HashMap<(NodeId, NodeId), InnovationId>,
+    /// Hidden node created when splitting every connection.
+    splits: HashMap<InnovationId, usize>,
+}
+
+impl Innovations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn num_connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn num_hidden_nodes(&self) -> usize {
+        self.splits.len()
+    }
+
+    fn connection(&mut self, from: NodeId, to: NodeId) -> InnovationId {
+        let next = self.connections.len();
+        *self.connections.entry((from, to)).or_insert(next)
+    }
+
+    /// Index of the hidden node, starting from zero.
+    fn split(&mut self, innovation: InnovationId) -> usize {
+        let next = self.splits.len();
+        *self.splits.entry(innovation).or_insert(next)
+    }
+}
+
+/// Probabilities of the mutations applied to the networks of the newborn cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NeatMutation {
+    /// Probability of connecting two unconnected nodes.
+    pub add_connection: Scalar,
+    /// Probability of splitting a connection with a new node.
+    pub add_node: Scalar,
+    /// Probability of perturbing every weight and bias.
+    pub perturb: Scalar,
+    /// Maximum change of the perturbed weights and biases.
+    pub perturb_power: Scalar,
+}
+
+impl Default for NeatMutation {
+    fn default() -> Self {
+        Self {
+            add_connection: 0.1,
+            add_node: 0.03,
+            perturb: 0.1,
+            perturb_power: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeGene {
+    pub bias: Scalar,
+    pub activation: ActivationFunction,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionGene {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub weight: Scalar,
+    pub enabled: bool,
+}
+
+/// Node ready to be evaluated, with the indices of its sources in the values.
+#[derive(Clone)]
+struct EvaluationNode {
+    index: usize,
+    bias: Scalar,
+    activation: ActivationFunction,
+    sources: Vec<(usize, Scalar)>,
+}
+
+/// Neuronal network with an evolvable topology, NEAT-style.
+/// Nodes `0..I` are the inputs and `I..I + O` the outputs. The hidden nodes come after them.
+#[derive(Clone)]
+pub struct NeatNetwork<const I: usize, const O: usize> {
+    /// Genes of the output and hidden nodes. The inputs don't have genes.
+    nodes: BTreeMap<NodeId, NodeGene>,
+    connections: BTreeMap<InnovationId, ConnectionGene>,
+    /// Nodes that affect the outputs, sorted so every node goes after its sources.
+    evaluation: Vec<EvaluationNode>,
+    /// Values of the inputs, followed by the values of the nodes in the same order as `nodes`.
+    values: Vec<Scalar>,
+}
+
+impl<const I: usize, const O: usize> NeatNetwork<I, O> {
+    /// Network without connections.
+    pub fn empty() -> Self {
+        let nodes = (I..I + O)
+            .map(|id| {
+                let node = NodeGene {
+                    bias: 0.0,
+                    activation: ActivationFunction::Tanh,
+                };
+                (id, node)
+            })
+            .collect();
+        let mut network = Self {
+            nodes,
+            connections: BTreeMap::new(),
+            evaluation: Vec::new(),
+            values: Vec::new(),
+        };
+        network.compile();
+        network
+    }
+
+    /// Network where every output is connected to a few random inputs.
+    pub fn random<R: Rng>(rng: &mut R, innovations: &mut Innovations) -> Self {
+        let mut network = Self::empty();
+        for node in network.nodes.values_mut() {
+            node.bias = rng.gen_range(-1.0..1.0);
+        }
+        for to in I..I + O {
+            for from in sample(rng, I, INITIAL_CONNECTIONS_PER_OUTPUT.min(I)) {
+                let connection = ConnectionGene {
+                    from,
+                    to,
+                    weight: rng.gen_range(-1.0..1.0),
+                    enabled: true,
+                };
+                network
+                    .connections
+                    .insert(innovations.connection(from, to), connection);
+            }
+        }
+        network.compile();
+        network
+    }
+
+    pub fn nodes(&self) -> &BTreeMap<NodeId, NodeGene> {
+        &self.nodes
+    }
+
+    pub fn connections(&self) -> &BTreeMap<InnovationId, ConnectionGene> {
+        &self.connections
+    }
+
+    /// Values of the nodes on the last evaluation, in the same order as `nodes`.
+    pub fn node_values(&self) -> &[Scalar] {
+        self.values.get(I..).unwrap_or_default()
+    }
+
+    /// Only the nodes that affect the outputs are evaluated.
+    /// The nodes without incoming connections output zero, as they don't work.
+    pub fn process(&mut self, inputs: &V<I>) -> V<O> {
+        let Self {
+            evaluation, values, ..
+        } = self;
+        values[..I].copy_from_slice(inputs.as_slice());
+        for node in evaluation.iter() {
+            if node.sources.is_empty() {
+                values[node.index] = 0.0;
+                continue;
+            }
+            let sum = node
+                .sources
+                .iter()
+                .map(|(source, weight)| weight * values[*source])
+                .sum::<Scalar>();
+            values[node.index] = node.activation.apply(sum + node.bias);
+        }
+        V::from_fn(|row, _| values[I + row])
+    }
+
+    /// Evaluated nodes with at least one incoming connection.
+    pub fn num_working_neurons(&self) -> Scalar {
+        self.evaluation
+            .iter()
+            .filter(|node| !node.sources.is_empty())
+            .count() as Scalar
+    }
+
+    pub(crate) fn mutate<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        innovations: &mut Innovations,
+        mutation: &NeatMutation,
+    ) {
+        let power = mutation.perturb_power.abs();
+        let perturb = mutation.perturb.clamp(0.0, 1.0);
+        for connection in self.connections.values_mut() {
+            if rng.gen_bool(perturb) {
+                connection.weight += rng.gen_range(-power..=power);
+            }
+        }
+        for node in self.nodes.values_mut() {
+            if rng.gen_bool(perturb) {
+                node.bias += rng.gen_range(-power..=power);
+            }
+        }
+        if rng.gen_bool(mutation.add_connection.clamp(0.0, 1.0)) {
+            self.add_connection(rng, innovations);
+        }
+        if rng.gen_bool(mutation.add_node.clamp(0.0, 1.0)) {
+            self.add_node(rng, innovations);
+        }
+        self.compile();
+    }
+
+    /// Changes the activation function of the hidden nodes.
+    pub(crate) fn mutate_activations<R: Rng>(&mut self, rng: &mut R, probability: Scalar) {
+        let mut mutated = false;
+        for (_, node) in self.nodes.range_mut(I + O..) {
+            if rng.gen_bool(probability) {
+                node.activation = ActivationFunction::random(rng);
+                mutated = true;
+            }
+        }
+        if mutated {
+            self.compile();
+        }
+    }
+
+    /// Disables random connections, hidden nodes or inputs, and restores random connections.
+    pub(crate) fn prune<R: Rng>(&mut self, rng: &mut R, pruning: &PruningMutation) {
+        if rng.gen_bool(pruning.connection.clamp(0.0, 1.0)) {
+            self.set_random_connection_enabled(rng, true, false);
+        }
+        if rng.gen_bool(pruning.neuron.clamp(0.0, 1.0)) {
+            let hidden = self.nodes.range(I + O..).map(|(id, _)| *id).choose(rng);
+            if let Some(node) = hidden {
+                self.disable_connections(|connection| connection.to == node);
+            }
+        }
+        if rng.gen_bool(pruning.input.clamp(0.0, 1.0)) {
+            let input = rng.gen_range(0..I);
+            self.disable_connections(|connection| connection.from == input);
+        }
+        if rng.gen_bool(pruning.restore.clamp(0.0, 1.0)) {
+            self.set_random_connection_enabled(rng, false, true);
+        }
+        self.compile();
+    }
+
+    fn set_random_connection_enabled<R: Rng>(&mut self, rng: &mut R, current: bool, enabled: bool) {
+        let connection = self
+            .connections
+            .values_mut()
+            .filter(|connection| connection.enabled == current)
+            .choose(rng);
+        if let Some(connection) = connection {
+            connection.enabled = enabled;
+        }
+    }
+
+    fn disable_connections(&mut self, filter: impl Fn(&ConnectionGene) -> bool) {
+        for connection in self.connections.values_mut() {
+            if filter(connection) {
+                connection.enabled = false;
+            }
+        }
+    }
+
+    /// Connects two random nodes, as long as it doesn't create a cycle.
+    fn add_connection<R: Rng>(&mut self, rng: &mut R, innovations: &mut Innovations) {
+        let sources = (0..I)
+            .chain(self.nodes.keys().copied().filter(|id| *id >= I + O))
+            .collect::<Vec<_>>();
+        let targets = self.nodes.keys().copied().collect::<Vec<_>>();
+        for _ in 0..ADD_CONNECTION_ATTEMPTS {
+            let (Some(from), Some(to)) = (sources.choose(rng), targets.choose(rng)) else {
+                return;
+            };
+            let connected = self
+                .connections
+                .values()
+                .any(|connection| connection.from == *from && connection.to == *to);
+            if from == to || connected || self.reaches(*to, *from) {
+                continue;
+            }
+            let connection = ConnectionGene {
+                from: *from,
+                to: *to,
+                weight: rng.gen_range(-1.0..1.0),
+                enabled: true,
+            };
+            self.connections
+                .insert(innovations.connection(*from, *to), connection);
+            return;
+        }
+    }
+
+    /// Replaces a random connection with a new node and two connections.
+    /// The incoming connection has a weight of one, so the behaviour barely changes.
+    fn add_node<R: Rng>(&mut self, rng: &mut R, innovations: &mut Innovations) {
+        let Some((innovation, from, to, weight)) = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.enabled)
+            .map(|(innovation, connection)| {
+                (
+                    *innovation,
+                    connection.from,
+                    connection.to,
+                    connection.weight,
+                )
+            })
+            .choose(rng)
+        else {
+            return;
+        };
+        let node_id = I + O + innovations.split(innovation);
+        if self.nodes.contains_key(&node_id) {
+            return;
+        }
+        if let Some(connection) = self.connections.get_mut(&innovation) {
+            connection.enabled = false;
+        }
+        let node = NodeGene {
+            bias: 0.0,
+            activation: ActivationFunction::random(rng),
+        };
+        self.nodes.insert(node_id, node);
+        for (from, to, weight) in [(from, node_id, 1.0), (node_id, to, weight)] {
+            let connection = ConnectionGene {
+                from,
+                to,
+                weight,
+                enabled: true,
+            };
+            self.connections
+                .insert(innovations.connection(from, to), connection);
+        }
+    }
+
+    /// Whether there is a path between both nodes.
+    fn reaches(&self, from: NodeId, to: NodeId) -> bool {
+        let mut visited = HashSet::new();
+        let mut pending = vec![from];
+        while let Some(node) = pending.pop() {
+            if node == to {
+                return true;
+            }
+            if visited.insert(node) {
+                pending.extend(
+                    self.connections
+                        .values()
+                        .filter(|connection| connection.from == node)
+                        .map(|connection| connection.to),
+                );
+            }
+        }
+        false
+    }
+
+    /// Sorts the nodes that affect the outputs for the evaluation.
+    /// Connections closing a cycle, which can only appear after a crossover, are ignored.
+    fn compile(&mut self) {
+        let mut incoming = HashMap::<NodeId, Vec<(NodeId, Scalar)>>::new();
+        for connection in self
+            .connections
+            .values()
+            .filter(|connection| connection.enabled)
+        {
+            incoming
+                .entry(connection.to)
+                .or_default()
+                .push((connection.from, connection.weight));
+        }
+
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        for output in I..I + O {
+            self.visit(output, &incoming, &mut visited, &mut order);
+        }
+
+        let indices = (0..I)
+            .map(|id| (id, id))
+            .chain(
+                self.nodes
+                    .keys()
+                    .enumerate()
+                    .map(|(position, id)| (*id, I + position)),
+            )
+            .collect::<HashMap<_, _>>();
+        let positions = order
+            .iter()
+            .enumerate()
+            .map(|(position, id)| (*id, position))
+            .collect::<HashMap<_, _>>();
+        self.evaluation = order
+            .iter()
+            .enumerate()
+            .map(|(position, id)| {
+                let node = &self.nodes[id];
+                let sources = incoming
+                    .get(id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|(source, _)| {
+                        *source < I
+                            || positions
+                                .get(source)
+                                .is_some_and(|source_position| *source_position < position)
+                    })
+                    .map(|(source, weight)| (indices[source], *weight))
+                    .collect();
+                EvaluationNode {
+                    index: indices[id],
+                    bias: node.bias,
+                    activation: node.activation,
+                    sources,
+                }
+            })
+            .collect();
+        self.values = vec![0.0; I + self.nodes.len()];
+    }
+
+    fn visit(
+        &self,
+        node: NodeId,
+        incoming: &HashMap<NodeId, Vec<(NodeId, Scalar)>>,
+        visited: &mut HashSet<NodeId>,
+        order: &mut Vec<NodeId>,
+    ) {
+        if node < I || !self.nodes.contains_key(&node) || !visited.insert(node) {
+            return;
+        }
+        for (source, _) in incoming.get(&node).into_iter().flatten() {
+            self.visit(*source, incoming, visited, order);
+        }
+        order.push(node);
+    }
+
+    fn is_valid_connection(&self, from: NodeId, to: NodeId) -> bool {
+        let valid_from = from < I || from >= I + O;
+        valid_from && to >= I && from != to
+    }
+}
+
+impl<const I: usize, const O: usize> BuildGenome for NeatNetwork<I, O> {
+    fn build_genome(&self, builder: GenomeBuilder) {
+        let nodes_builder = builder.nested("nodes");
+        for (id, node) in self.nodes.iter() {
+            let node_builder = nodes_builder.nested(&format!("{id:05}"));
+            node_builder.add("bias", Gen::new(node.bias));
+            node.activation
+                .build_genome(node_builder.nested("activation"));
+        }
+        let connections_builder = builder.nested("connections");
+        for (innovation, connection) in self.connections.iter() {
+            let connection_builder = connections_builder.nested(&format!("{innovation:05}"));
+            let enabled = if connection.enabled { 1.0 } else { 0.0 };
+            connection_builder.add("from", Gen::discrete(connection.from as Scalar));
+            connection_builder.add("to", Gen::discrete(connection.to as Scalar));
+            connection_builder.add("weight", Gen::new(connection.weight));
+            connection_builder.add("enabled", Gen::discrete(enabled));
+        }
+    }
+}
+
+/// The genes describe the whole network, so the hidden nodes and the connections
+/// missing from them are removed. Nothing changes when there are no genes at all.
+impl<const I: usize, const O: usize> ApplyGenome for NeatNetwork<I, O> {
+    fn apply_genome(&mut self, reader: GenomeReader<'_>) {
+        if reader.children().is_empty() {
+            return;
+        }
+        let mut genome_nodes = HashSet::new();
+        let mut genome_connections = HashSet::new();
+
+        let nodes_reader = reader.nested("nodes");
+        for name in nodes_reader.children() {
+            let Ok(id) = name.parse::<NodeId>() else {
+                continue;
+            };
+            if id < I {
+                continue;
+            }
+            genome_nodes.insert(id);
+            let node_reader = nodes_reader.nested(&name);
+            let node = self.nodes.entry(id).or_insert(NodeGene {
+                bias: 0.0,
+                activation: ActivationFunction::Tanh,
+            });
+            if let Some(bias) = node_reader.get("bias") {
+                node.bias = bias;
+            }
+            node.activation
+                .apply_genome(node_reader.nested("activation"));
+        }
+
+        let connections_reader = reader.nested("connections");
+        for name in connections_reader.children() {
+            let Ok(innovation) = name.parse::<InnovationId>() else {
+                continue;
+            };
+            let connection_reader = connections_reader.nested(&name);
+            let from = connection_reader.get("from").map(|from| from.round());
+            let to = connection_reader.get("to").map(|to| to.round());
+            let Some((from, to)) = from.zip(to).filter(|(from, to)| *from >= 0.0 && *to >= 0.0)
+            else {
+                continue;
+            };
+            let (from, to) = (from as NodeId, to as NodeId);
+            if !self.is_valid_connection(from, to) {
+                continue;
+            }
+            for id in [from, to].into_iter().filter(|id| *id >= I) {
+                genome_nodes.insert(id);
+                self.nodes.entry(id).or_insert(NodeGene {
+                    bias: 0.0,
+                    activation: ActivationFunction::Tanh,
+                });
+            }
+            genome_connections.insert(innovation);
+            let connection = self
+                .connections
+                .entry(innovation)
+                .or_insert(ConnectionGene {
+                    from,
+                    to,
+                    weight: 0.0,
+                    enabled: true,
+                });
+            connection.from = from;
+            connection.to = to;
+            if let Some(weight) = connection_reader.get("weight") {
+                connection.weight = weight;
+            }
+            if let Some(enabled) = connection_reader.get("enabled") {
+                connection.enabled = enabled >= 0.5;
+            }
+        }
+
+        self.nodes
+            .retain(|id, _| *id < I + O || genome_nodes.contains(id));
+        self.connections
+            .retain(|innovation, _| genome_connections.contains(innovation));
+        self.compile();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genome::Genome;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    type Network = NeatNetwork<2, 1>;
+
+    const OUTPUT: NodeId = 2;
+    const HIDDEN: NodeId = 3;
+
+    fn connect(network: &mut Network, innovations: &mut Innovations, from: NodeId, to: NodeId) {
+        let connection = ConnectionGene {
+            from,
+            to,
+            weight: 1.0,
+            enabled: true,
+        };
+        network
+            .connections
+            .insert(innovations.connection(from, to), connection);
+    }
+
+    fn add_hidden(network: &mut Network, id: NodeId) {
+        let node = NodeGene {
+            bias: 0.0,
+            activation: ActivationFunction::Linear,
+        };
+        network.nodes.insert(id, node);
+    }
+
+    fn genome(network: &Network) -> Genome {
+        let builder = GenomeBuilder::new();
+        network.build_genome(builder.clone());
+        builder.build()
+    }
+
+    fn has_cycle(network: &Network) -> bool {
+        network
+            .connections
+            .values()
+            .any(|connection| network.reaches(connection.to, connection.from))
+    }
+
+    #[test]
+    fn add_node_splits_a_connection() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut innovations = Innovations::new();
+        let mut network = Network::empty();
+        connect(&mut network, &mut innovations, 0, OUTPUT);
+        network.connections.get_mut(&0).unwrap().weight = 0.5;
+
+        network.add_node(&mut rng, &mut innovations);
+
+        assert!(network.nodes.contains_key(&HIDDEN));
+        assert!(!network.connections[&0].enabled);
+        let weights: Vec<_> = network
+            .connections
+            .values()
+            .filter(|connection| connection.enabled)
+            .map(|connection| (connection.from, connection.to, connection.weight))
+            .collect();
+        assert_eq!(weights, vec![(0, HIDDEN, 1.0), (HIDDEN, OUTPUT, 0.5)]);
+        assert_eq!(innovations.num_hidden_nodes(), 1);
+    }
+
+    #[test]
+    fn add_connection_connects_valid_nodes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut innovations = Innovations::new();
+        let mut network = Network::empty();
+
+        network.add_connection(&mut rng, &mut innovations);
+
+        let connection = network.connections.values().next().unwrap();
+        assert!(connection.from < 2 && connection.to == OUTPUT);
+    }
+
+    #[test]
+    fn add_connection_rejects_cycles() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut innovations = Innovations::new();
+        let mut network = Network::empty();
+        add_hidden(&mut network, HIDDEN);
+        add_hidden(&mut network, HIDDEN + 1);
+        connect(&mut network, &mut innovations, HIDDEN, HIDDEN + 1);
+        connect(&mut network, &mut innovations, HIDDEN + 1, OUTPUT);
+
+        for _ in 0..100 {
+            network.add_connection(&mut rng, &mut innovations);
+        }
+
+        assert!(!has_cycle(&network));
+        assert!(network.reaches(HIDDEN, OUTPUT));
+        assert!(!network.reaches(HIDDEN + 1, HIDDEN));
+    }
+
+    #[test]
+    fn nodes_are_evaluated_after_their_sources() {
+        let mut innovations = Innovations::new();
+        let mut network = Network::empty();
+        network.nodes.get_mut(&OUTPUT).unwrap().activation = ActivationFunction::Linear;
+        add_hidden(&mut network, HIDDEN + 1);
+        add_hidden(&mut network, HIDDEN);
+        connect(&mut network, &mut innovations, 0, HIDDEN + 1);
+        connect(&mut network, &mut innovations, HIDDEN + 1, HIDDEN);
+        connect(&mut network, &mut innovations, HIDDEN, OUTPUT);
+        connect(&mut network, &mut innovations, 1, OUTPUT);
+        network.compile();
+
+        let output = network.process(&V::from([2.0, 3.0]));
+
+        assert_eq!(output[0], 5.0);
+        let order: Vec<_> = network.evaluation.iter().map(|node| node.index).collect();
+        // Values are indexed by position: the inputs, then the output and the hidden nodes
+        assert_eq!(order, vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn genome_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut innovations = Innovations::new();
+        let mut network = Network::random(&mut rng, &mut innovations);
+        network.add_node(&mut rng, &mut innovations);
+        network.add_connection(&mut rng, &mut innovations);
+        network.compile();
+
+        let mut copy = Network::empty();
+        copy.apply_genome(genome(&network).reader());
+
+        assert_eq!(
+            network.nodes.keys().collect::<Vec<_>>(),
+            copy.nodes.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(genome(&network).distance(&genome(&copy)), 0.0);
+        let inputs = V::from([0.3, -0.7]);
+        assert_eq!(network.process(&inputs), copy.process(&inputs));
+    }
+
+    #[test]
+    fn applying_a_smaller_genome_removes_stale_structure() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut innovations = Innovations::new();
+        let mut small = Network::empty();
+        connect(&mut small, &mut innovations, 0, OUTPUT);
+        small.compile();
+        let mut large = small.clone();
+        large.add_node(&mut rng, &mut innovations);
+        connect(&mut large, &mut innovations, 1, HIDDEN);
+        large.compile();
+
+        large.apply_genome(genome(&small).reader());
+
+        assert_eq!(large.nodes.keys().collect::<Vec<_>>(), vec![&OUTPUT]);
+        assert_eq!(large.connections.keys().collect::<Vec<_>>(), vec![&0]);
+        assert!(large.connections[&0].enabled);
+    }
+}
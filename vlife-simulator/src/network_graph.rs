@@ -0,0 +1,245 @@
+use std::io::{self, Write};
+
+use crate::neurons::ActivationFunction;
+use crate::Scalar;
+
+/// Snapshot of a neuronal network as a graph, used to inspect and export it.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphNodeKind {
+    Input,
+    Hidden,
+    Output,
+}
+
+impl GraphNodeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Input => "input",
+            Self::Hidden => "hidden",
+            Self::Output => "output",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    pub id: usize,
+    /// Name of the input or output, like `molecules_proportion[3]`.
+    pub label: String,
+    pub kind: GraphNodeKind,
+    /// Depth of the node. The inputs are in layer zero.
+    pub layer: usize,
+    /// The inputs don't have bias nor activation function.
+    pub bias: Option<Scalar>,
+    pub activation: Option<ActivationFunction>,
+    /// Output of the node on the last step. The inputs have the normalized values.
+    pub value: Scalar,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphEdge {
+    pub from: usize,
+    pub to: usize,
+    pub weight: Scalar,
+    pub enabled: bool,
+}
+
+impl NetworkGraph {
+    /// Writes the graph in the Graphviz DOT format.
+    /// Positive weights are blue and negative ones red, with the width growing with the magnitude.
+    /// Disabled connections are dashed.
+    pub fn write_dot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "digraph network {{")?;
+        writeln!(writer, "  rankdir=LR;")?;
+        writeln!(writer, "  node [shape=circle, fontsize=10];")?;
+
+        let num_layers = self.nodes.iter().map(|node| node.layer + 1).max();
+        for layer in 0..num_layers.unwrap_or_default() {
+            writeln!(writer, "  {{")?;
+            writeln!(writer, "    rank=same;")?;
+            for node in self.nodes.iter().filter(|node| node.layer == layer) {
+                let shape = match node.kind {
+                    GraphNodeKind::Input | GraphNodeKind::Output => "box",
+                    GraphNodeKind::Hidden => "circle",
+                };
+                let label = match node.activation {
+                    Some(activation) => format!("{}\\n{:?}", escape(&node.label), activation),
+                    None => escape(&node.label),
+                };
+                writeln!(
+                    writer,
+                    "    n{} [label=\"{}\", shape={}];",
+                    node.id, label, shape
+                )?;
+            }
+            writeln!(writer, "  }}")?;
+        }
+
+        for edge in self.edges.iter() {
+            let color = if edge.weight >= 0.0 {
+                "#2060c0"
+            } else {
+                "#c03020"
+            };
+            let style = if edge.enabled { "solid" } else { "dashed" };
+            let width = 0.5 + edge.weight.abs().min(4.0);
+            writeln!(
+                writer,
+                "  n{} -> n{} [label=\"{:.2}\", color=\"{}\", style={}, penwidth={:.2}];",
+                edge.from, edge.to, edge.weight, color, style, width
+            )?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    /// Writes the graph as a JSON object with the lists of `nodes` and `edges`.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"nodes\": [")?;
+        for (index, node) in self.nodes.iter().enumerate() {
+            let separator = if index + 1 < self.nodes.len() {
+                ","
+            } else {
+                ""
+            };
+            let activation = node
+                .activation
+                .map(|activation| format!("\"{activation:?}\""))
+                .unwrap_or_else(|| "null".to_string());
+            writeln!(
+                writer,
+                "    {{\"id\": {}, \"label\": \"{}\", \"kind\": \"{}\", \"layer\": {}, \"bias\": {}, \"activation\": {}, \"value\": {}}}{}",
+                node.id,
+                escape(&node.label),
+                node.kind.name(),
+                node.layer,
+                json_number(node.bias),
+                activation,
+                json_number(Some(node.value)),
+                separator
+            )?;
+        }
+        writeln!(writer, "  ],")?;
+        writeln!(writer, "  \"edges\": [")?;
+        for (index, edge) in self.edges.iter().enumerate() {
+            let separator = if index + 1 < self.edges.len() {
+                ","
+            } else {
+                ""
+            };
+            writeln!(
+                writer,
+                "    {{\"from\": {}, \"to\": {}, \"weight\": {}, \"enabled\": {}}}{}",
+                edge.from,
+                edge.to,
+                json_number(Some(edge.weight)),
+                edge.enabled,
+                separator
+            )?;
+        }
+        writeln!(writer, "  ]")?;
+        writeln!(writer, "}}")
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// JSON doesn't support NaN nor infinite numbers.
+fn json_number(value: Option<Scalar>) -> String {
+    match value {
+        Some(value) if value.is_finite() => value.to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> NetworkGraph {
+        NetworkGraph {
+            nodes: vec![
+                GraphNode {
+                    id: 0,
+                    label: r#"a"b\c"#.to_string(),
+                    kind: GraphNodeKind::Input,
+                    layer: 0,
+                    bias: None,
+                    activation: None,
+                    value: 2.0,
+                },
+                GraphNode {
+                    id: 1,
+                    label: "out".to_string(),
+                    kind: GraphNodeKind::Output,
+                    layer: 1,
+                    bias: Some(0.5),
+                    activation: Some(ActivationFunction::Tanh),
+                    value: Scalar::NAN,
+                },
+            ],
+            edges: vec![
+                GraphEdge {
+                    from: 0,
+                    to: 1,
+                    weight: 1.5,
+                    enabled: true,
+                },
+                GraphEdge {
+                    from: 0,
+                    to: 1,
+                    weight: -0.25,
+                    enabled: false,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn graphs_are_written_as_dot() {
+        let mut dot = Vec::new();
+        graph().write_dot(&mut dot).unwrap();
+        let expected = r##"digraph network {
+  rankdir=LR;
+  node [shape=circle, fontsize=10];
+  {
+    rank=same;
+    n0 [label="a\"b\\c", shape=box];
+  }
+  {
+    rank=same;
+    n1 [label="out\ntanh", shape=box];
+  }
+  n0 -> n1 [label="1.50", color="#2060c0", style=solid, penwidth=2.00];
+  n0 -> n1 [label="-0.25", color="#c03020", style=dashed, penwidth=0.75];
+}
+"##;
+        assert_eq!(String::from_utf8(dot).unwrap(), expected);
+    }
+
+    #[test]
+    fn graphs_are_written_as_json() {
+        let mut json = Vec::new();
+        graph().write_json(&mut json).unwrap();
+        let expected = r#"{
+  "nodes": [
+    {"id": 0, "label": "a\"b\\c", "kind": "input", "layer": 0, "bias": null, "activation": null, "value": 2},
+    {"id": 1, "label": "out", "kind": "output", "layer": 1, "bias": 0.5, "activation": "tanh", "value": null}
+  ],
+  "edges": [
+    {"from": 0, "to": 1, "weight": 1.5, "enabled": true},
+    {"from": 0, "to": 1, "weight": -0.25, "enabled": false}
+  ]
+}
+"#;
+        assert_eq!(String::from_utf8(json).unwrap(), expected);
+    }
+}
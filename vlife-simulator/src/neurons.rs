@@ -1,47 +1,56 @@
+use nalgebra::{Const, Dyn, OMatrix};
 use rand::{seq::SliceRandom, Rng};
-use vlife_macros::BuildGenome;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, Range};
+use vlife_macros::{ApplyGenome, BuildGenome};
 
-use crate::genome::{BuildGenome, Gen, GenomeBuilder};
+use crate::cell::{MAX_ENERGY, MAX_MOLECULE_AMOUNT, MAX_SIZE, MAX_SPEED, NUM_MOLECULES};
+use crate::controller::Controller;
+use crate::genome::{ApplyGenome, BuildGenome, Gen, GenomeBuilder, GenomeReader};
+use crate::neat::{Innovations, NeatMutation, NeatNetwork, NodeId};
+use crate::network_graph::{GraphEdge, GraphNode, GraphNodeKind, NetworkGraph};
 use crate::Scalar;
-use crate::{cell::NUM_MOLECULES, VView, M, V};
+use crate::{VView, M, V};
 
 macro_rules! define_inputs {
-    ( $name:ident $(,)?) => {
-        define_inputs!(@next 0, [$name]);
-
-    };
-
-    ( $name:ident, $($args:tt),* $(,)?) => {
-        define_inputs!(@next 0, [$name, $($args),*]);
-    };
-
-    ( ($name:ident, $len:expr) $(,)?) => {
-        define_inputs!(@next 0, [($name, $len)]);
-
-    };
-
-    ( ($name:ident, $len:expr), $($args:tt),* $(,)?) => {
-        define_inputs!(@next 0, [($name, $len), $($args),*]);
-    };
-
-    (@next $start:expr, [$name:ident $(,)?]) => {
+    // The fields are accumulated until the last one, to build the table of descriptors.
+    // Every field can be followed by its normalization, like `age => Normalization::Running`
+    (@next $start:expr, [$(#[doc = $doc:literal])* $name:ident $(=> $normalization:expr)? $(,)?], [$($fields:tt)*]) => {
         define_inputs!(@scalar $name, $start);
         const NUM_INPUTS: usize = $start + 1;
+        const INPUTS: &[InputField] = &[
+            $($fields)*
+            InputField::new(stringify!($name), $start, 1, concat!($($doc),*), define_inputs!(@normalization $($normalization)?)),
+        ];
     };
 
-    (@next $start:expr, [$name:ident, $($args:tt),* $(,)?]) => {
+    (@next $start:expr, [$(#[doc = $doc:literal])* $name:ident $(=> $normalization:expr)?, $($args:tt)+], [$($fields:tt)*]) => {
         define_inputs!(@scalar $name, $start);
-        define_inputs!(@next $start + 1, [$($args),*]);
+        define_inputs!(
+            @next $start + 1,
+            [$($args)+],
+            [$($fields)* InputField::new(stringify!($name), $start, 1, concat!($($doc),*), define_inputs!(@normalization $($normalization)?)),]
+        );
     };
 
-    (@next $start:expr, [($name:ident, $len:expr) $(,)?]) => {
+    (@next $start:expr, [$(#[doc = $doc:literal])* ($name:ident, $len:expr) $(=> $normalization:expr)? $(,)?], [$($fields:tt)*]) => {
         define_inputs!(@vector $name, $start, $len);
         const NUM_INPUTS: usize = $start + $len;
+        const INPUTS: &[InputField] = &[
+            $($fields)*
+            InputField::new(stringify!($name), $start, $len, concat!($($doc),*), define_inputs!(@normalization $($normalization)?)),
+        ];
     };
 
-    (@next $start:expr, [($name:ident, $len:expr), $($args:tt),* $(,)?]) => {
+    (@next $start:expr, [$(#[doc = $doc:literal])* ($name:ident, $len:expr) $(=> $normalization:expr)?, $($args:tt)+], [$($fields:tt)*]) => {
         define_inputs!(@vector $name, $start, $len);
-        define_inputs!(@next $start + $len, [$($args),*]);
+        define_inputs!(
+            @next $start + $len,
+            [$($args)+],
+            [$($fields)* InputField::new(stringify!($name), $start, $len, concat!($($doc),*), define_inputs!(@normalization $($normalization)?)),]
+        );
     };
 
     (@scalar $name:ident, $start:expr) => {
@@ -63,52 +72,63 @@ macro_rules! define_inputs {
             }
         }
     };
-}
-
-macro_rules! define_outputs {
-    ( $name:ident $(,)?) => {
-        define_outputs!(@next 0, [$name]);
 
+    (@normalization) => {
+        Normalization::None
     };
 
-    ( $name:ident, $($args:tt),* $(,)?) => {
-        define_outputs!(@next 0, [$name, $($args),*]);
-    };
-
-    ( ($name:ident, $len:expr) $(,)?) => {
-        define_outputs!(@next 0, [($name, $len)]);
-
+    (@normalization $normalization:expr) => {
+        $normalization
     };
 
-    ( ($name:ident, $len:expr), $($args:tt),* $(,)?) => {
-        define_outputs!(@next 0, [($name, $len), $($args),*]);
+    ($($args:tt)+) => {
+        define_inputs!(@next 0, [$($args)+], []);
     };
+}
 
-    (@next $start:expr, [$name:ident $(,)?]) => {
+macro_rules! define_outputs {
+    // The fields are accumulated until the last one, to build the table of descriptors
+    (@next $start:expr, [$(#[doc = $doc:literal])* $name:ident $(,)?], [$($fields:tt)*]) => {
         define_outputs!(@scalar $name, $start);
         const NUM_OUTPUTS: usize = $start + 1;
+        const OUTPUTS: &[NeuronsField] = &[
+            $($fields)*
+            NeuronsField::new(stringify!($name), $start, 1, concat!($($doc),*)),
+        ];
     };
 
-    (@next $start:expr, [$name:ident, $($args:tt),* $(,)?]) => {
+    (@next $start:expr, [$(#[doc = $doc:literal])* $name:ident, $($args:tt)+], [$($fields:tt)*]) => {
         define_outputs!(@scalar $name, $start);
-        define_outputs!(@next $start + 1, [$($args),*]);
+        define_outputs!(
+            @next $start + 1,
+            [$($args)+],
+            [$($fields)* NeuronsField::new(stringify!($name), $start, 1, concat!($($doc),*)),]
+        );
     };
 
-    (@next $start:expr, [($name:ident, $len:expr) $(,)?]) => {
+    (@next $start:expr, [$(#[doc = $doc:literal])* ($name:ident, $len:expr) $(,)?], [$($fields:tt)*]) => {
         define_outputs!(@vector $name, $start, $len);
         const NUM_OUTPUTS: usize = $start + $len;
+        const OUTPUTS: &[NeuronsField] = &[
+            $($fields)*
+            NeuronsField::new(stringify!($name), $start, $len, concat!($($doc),*)),
+        ];
     };
 
-    (@next $start:expr, [($name:ident, $len:expr), $($args:tt),* $(,)?]) => {
+    (@next $start:expr, [$(#[doc = $doc:literal])* ($name:ident, $len:expr), $($args:tt)+], [$($fields:tt)*]) => {
         define_outputs!(@vector $name, $start, $len);
-        define_outputs!(@next $start + $len, [$($args),*]);
+        define_outputs!(
+            @next $start + $len,
+            [$($args)+],
+            [$($fields)* NeuronsField::new(stringify!($name), $start, $len, concat!($($doc),*)),]
+        );
     };
 
     (@scalar $name:ident, $start:expr) => {
         paste::paste! {
             impl Neurons {
                 pub fn [<get_ $name>](&self) -> Scalar {
-                    self.output_layer.outputs()[$start]
+                    self.outputs[$start]
                 }
             }
         }
@@ -118,60 +138,773 @@ macro_rules! define_outputs {
         paste::paste! {
             impl Neurons {
                 pub fn [<get_ $name>](&self) -> VView<'_, $len, NUM_OUTPUTS> {
-                    let outputs = self.output_layer.outputs();
-                    outputs.fixed_rows::<$len>($start)
+                    self.outputs.fixed_rows::<$len>($start)
                 }
             }
         }
     };
+
+    ($($args:tt)+) => {
+        define_outputs!(@next 0, [$($args)+], []);
+    };
 }
 
 const NUM_PROCESSING: usize = NUM_INPUTS / 2;
+/// Maximum learning rate of the random networks.
+pub const MAX_LEARNING_RATE: Scalar = 0.1;
+/// Maximum change of every weight learned during the life of a cell.
+pub const MAX_LEARNED_WEIGHT: Scalar = 1.0;
+/// Maximum proportion of enabled connections of a layer to use the sparse evaluation.
+pub const SPARSE_DENSITY: Scalar = 0.25;
+/// Number of memory cells. Their outputs are fed back as inputs on the next step.
+pub const NUM_MEMORY: usize = 4;
+/// Minimum number of networks evaluated by every thread of the batched evaluation.
+pub const MIN_BATCH_SIZE: usize = 256;
+
+/// Values of a layer for many networks evaluated together, with a column for every network.
+type Batch<const R: usize> = OMatrix<Scalar, Const<R>, Dyn>;
+/// Outputs of the layers of a layered network, evaluated for many networks together.
+type LayeredBatchOutputs = (
+    Batch<NUM_PROCESSING>,
+    Batch<NUM_PROCESSING>,
+    Batch<NUM_OUTPUTS>,
+);
+
+/// Number of steps averaged by the running normalization.
+pub const NORMALIZATION_WINDOW: Scalar = 1000.0;
+/// Maximum magnitude of the values normalized with the running mean and variance.
+pub const MAX_NORMALIZED_VALUE: Scalar = 5.0;
+/// Scale of the age input, so a minute is one.
+const AGE_SCALE: Scalar = 1.0 / 60.0;
+
+/// How an input is transformed before feeding it to the network,
+/// so large values don't saturate the neurons.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// The value is used as is.
+    None,
+    /// The value is transformed to `(value - offset) * scale`.
+    Static { scale: Scalar, offset: Scalar },
+    /// Standard score using the running mean and variance of the values seen by the cell.
+    Running,
+}
+
+impl Normalization {
+    pub const fn scale(scale: Scalar) -> Self {
+        Self::Static { scale, offset: 0.0 }
+    }
+}
+
+/// Running mean and variance of the inputs, weighting the last steps up to the window.
+#[derive(Clone)]
+struct InputStatistics {
+    mean: V<NUM_INPUTS>,
+    variance: V<NUM_INPUTS>,
+    count: Scalar,
+}
+
+impl InputStatistics {
+    fn new() -> Self {
+        Self {
+            mean: V::zeros(),
+            variance: V::zeros(),
+            count: 0.0,
+        }
+    }
+
+    fn update(&mut self, inputs: &V<NUM_INPUTS>) {
+        self.count = (self.count + 1.0).min(NORMALIZATION_WINDOW);
+        let rate = 1.0 / self.count;
+        for ((mean, variance), &x) in self
+            .mean
+            .iter_mut()
+            .zip(self.variance.iter_mut())
+            .zip(inputs.iter())
+        {
+            let delta = x - *mean;
+            *mean += rate * delta;
+            *variance += rate * (delta * (x - *mean) - *variance);
+        }
+    }
+
+    fn standard_score(&self, index: usize, x: Scalar) -> Scalar {
+        let deviation = self.variance[index].sqrt();
+        if deviation > 0.0 {
+            ((x - self.mean[index]) / deviation).clamp(-MAX_NORMALIZED_VALUE, MAX_NORMALIZED_VALUE)
+        } else {
+            0.0
+        }
+    }
+}
 
-#[derive(Clone, BuildGenome)]
+/// Description of an input or output of the neuronal networks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NeuronsField {
+    pub name: &'static str,
+    /// Index of the first value in the inputs or outputs.
+    pub start: usize,
+    /// Number of values.
+    pub len: usize,
+    description: &'static str,
+}
+
+impl NeuronsField {
+    const fn new(name: &'static str, start: usize, len: usize, description: &'static str) -> Self {
+        Self {
+            name,
+            start,
+            len,
+            description,
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.len
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.description.trim()
+    }
+
+    /// Name of the value in the index, like `molecules_proportion[3]`.
+    fn label<'a>(fields: impl IntoIterator<Item = &'a NeuronsField>, index: usize) -> String {
+        fields
+            .into_iter()
+            .find(|field| field.range().contains(&index))
+            .map(|field| {
+                if field.len > 1 {
+                    format!("{}[{}]", field.name, index - field.start)
+                } else {
+                    field.name.to_string()
+                }
+            })
+            .unwrap_or_else(|| index.to_string())
+    }
+}
+
+/// Description of an input of the neuronal networks, with its normalization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputField {
+    field: NeuronsField,
+    pub normalization: Normalization,
+}
+
+impl InputField {
+    const fn new(
+        name: &'static str,
+        start: usize,
+        len: usize,
+        description: &'static str,
+        normalization: Normalization,
+    ) -> Self {
+        Self {
+            field: NeuronsField::new(name, start, len, description),
+            normalization,
+        }
+    }
+}
+
+impl Deref for InputField {
+    type Target = NeuronsField;
+
+    fn deref(&self) -> &Self::Target {
+        &self.field
+    }
+}
+
+/// Index of the first value of the memory in the inputs.
+const MEMORY_INPUT: usize = input_start("memory");
+
+/// Index of the first value of the input with the name, found when compiling.
+const fn input_start(name: &str) -> usize {
+    let mut index = 0;
+    while index < INPUTS.len() {
+        let field = &INPUTS[index].field;
+        if field.name.len() == name.len() {
+            let (bytes1, bytes2) = (field.name.as_bytes(), name.as_bytes());
+            let mut position = 0;
+            while position < bytes1.len() && bytes1[position] == bytes2[position] {
+                position += 1;
+            }
+            if position == bytes1.len() {
+                return field.start;
+            }
+        }
+        index += 1;
+    }
+    panic!("no input with the name");
+}
+
+/// Kind of neuronal network used by the cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrainType {
+    /// Fixed architecture with fully connected layers.
+    #[default]
+    Layered,
+    /// Evolvable topology, where the genome encodes the nodes and connections.
+    Neat,
+}
+
+/// Default probability of changing the activation function of every hidden neuron.
+/// Activation functions don't mutate by default, so divisions make exact copies.
+pub const DEFAULT_ACTIVATION_MUTATION: Scalar = 0.0;
+
+/// Mutations applied to the neuronal networks of the newborn and recombined cells.
+pub(crate) struct NeuronsMutation {
+    pub(crate) innovations: Innovations,
+    pub(crate) neat: NeatMutation,
+    /// Probability of changing the activation function of every hidden neuron.
+    pub(crate) activation: Scalar,
+    pub(crate) pruning: PruningMutation,
+}
+
+impl NeuronsMutation {
+    pub(crate) fn apply<R: Rng>(&mut self, rng: &mut R, neurons: &mut Neurons) {
+        neurons.mutate_topology(rng, &mut self.innovations, &self.neat);
+        neurons.mutate_activations(rng, self.activation);
+        neurons.prune(rng, &self.pruning);
+    }
+}
+
+/// Probabilities of the structural mutations that disable parts of the networks,
+/// so they can get rid of the cost of the neurons they don't need.
+/// For the layered networks they are applied to every layer.
+/// All the probabilities are zero by default, like [`DEFAULT_ACTIVATION_MUTATION`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PruningMutation {
+    /// Probability of disabling a random connection.
+    pub connection: Scalar,
+    /// Probability of disabling all the incoming connections of a random hidden neuron.
+    pub neuron: Scalar,
+    /// Probability of disabling all the connections from a random input.
+    pub input: Scalar,
+    /// Probability of enabling a random connection again.
+    pub restore: Scalar,
+}
+
+impl Default for NeuronsMutation {
+    fn default() -> Self {
+        Self {
+            innovations: Innovations::new(),
+            neat: NeatMutation::default(),
+            activation: DEFAULT_ACTIVATION_MUTATION,
+            pruning: PruningMutation::default(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Neurons {
     inputs: V<NUM_INPUTS>,
+    /// Inputs fed to the network, after the normalization.
+    normalized_inputs: V<NUM_INPUTS>,
+    input_statistics: InputStatistics,
+    outputs: V<NUM_OUTPUTS>,
+    brain: Brain,
+    /// Proportion of the previous memory value kept on every step, between 0 and 1.
+    /// The rest is replaced by the memory output of the network.
+    memory_retention: V<NUM_MEMORY>,
+    working_neurons: Scalar,
+}
+
+#[derive(Clone)]
+enum Brain {
+    Layered(Box<LayeredNetwork>),
+    Neat(NeatNetwork<NUM_INPUTS, NUM_OUTPUTS>),
+}
+
+#[derive(Clone, BuildGenome, ApplyGenome)]
+struct LayeredNetwork {
     #[build_genome(nested)]
     input_layer: Layer<NUM_INPUTS, NUM_PROCESSING>,
     #[build_genome(nested)]
     processing_layer: Layer<NUM_PROCESSING, NUM_PROCESSING>,
     #[build_genome(nested)]
     output_layer: Layer<NUM_PROCESSING, NUM_OUTPUTS>,
-    working_neurons: Scalar,
 }
 
-impl Neurons {
-    pub fn random() -> Self {
-        let mut input_layer = Layer::random();
-        input_layer.activation = ActivationFunction::Sigmoid;
-        let mut processing_layer = Layer::random();
-        processing_layer.activation = ActivationFunction::Tanh;
-        let mut output_layer = Layer::random();
-        output_layer.activation = ActivationFunction::Tanh;
-        let working_neurons = input_layer.num_working_neurons()
-            + processing_layer.num_working_neurons()
-            + output_layer.num_working_neurons();
+impl LayeredNetwork {
+    /// The output neurons always use `Tanh`, so the outputs are bounded.
+    fn random<R: Rng>(rng: &mut R) -> Self {
+        let mut output_layer = Layer::random(rng);
+        output_layer.activations = [ActivationFunction::Tanh; NUM_OUTPUTS];
         Self {
-            inputs: V::zeros(),
-            input_layer,
-            processing_layer,
+            input_layer: Layer::random(rng),
+            processing_layer: Layer::random(rng),
             output_layer,
-            working_neurons,
         }
     }
 
-    pub fn num_working_neurons(&self) -> Scalar {
-        self.working_neurons
+    /// Network without weights, to apply a genome to it.
+    fn empty() -> Self {
+        Self {
+            input_layer: Layer::empty(),
+            processing_layer: Layer::empty(),
+            output_layer: Layer::empty(),
+        }
     }
 
-    pub fn process(&mut self) {
-        // println!("IN: {:.2}", self.inputs.transpose());
-        self.input_layer.process(&self.inputs);
+    fn num_working_neurons(&self) -> Scalar {
+        self.input_layer.num_working_neurons()
+            + self.processing_layer.num_working_neurons()
+            + self.output_layer.num_working_neurons()
+    }
+
+    fn process(&mut self, inputs: &V<NUM_INPUTS>) -> V<NUM_OUTPUTS> {
+        // println!("IN: {:.2}", inputs.transpose());
+        self.input_layer.process(inputs);
         // println!("INL: {:.2}", self.input_layer.outputs().transpose());
         self.processing_layer.process(self.input_layer.outputs());
         // println!("HIL: {:.2}", self.processing_layer.outputs().transpose());
         self.output_layer.process(self.processing_layer.outputs());
         // println!("OUL: {:.2}", self.output_layer.outputs().transpose());
+        *self.output_layer.outputs()
+    }
+
+    /// Same as `process` for many copies of the network, with a column of `inputs` for every copy,
+    /// returning the outputs of every layer for every copy.
+    fn process_batch(&self, inputs: &Batch<NUM_INPUTS>) -> LayeredBatchOutputs {
+        let hidden1 = self.input_layer.process_batch(inputs);
+        let hidden2 = self.processing_layer.process_batch(&hidden1);
+        let outputs = self.output_layer.process_batch(&hidden2);
+        (hidden1, hidden2, outputs)
+    }
+
+    /// Identifies the weights, biases and activation functions of all the layers.
+    fn fingerprint(&self) -> [u64; 3] {
+        [
+            self.input_layer.fingerprint,
+            self.processing_layer.fingerprint,
+            self.output_layer.fingerprint,
+        ]
+    }
+
+    fn learn(&mut self, inputs: &V<NUM_INPUTS>, reward: Scalar) {
+        self.input_layer.learn(inputs, reward);
+        self.processing_layer
+            .learn(&self.input_layer.outputs, reward);
+        self.output_layer
+            .learn(&self.processing_layer.outputs, reward);
+    }
+
+    fn add_to_graph(&self, graph: &mut NetworkGraph) {
+        let hidden1 = NUM_INPUTS;
+        let hidden2 = hidden1 + NUM_PROCESSING;
+        let outputs = hidden2 + NUM_PROCESSING;
+        self.input_layer
+            .add_to_graph(graph, 1, 0, hidden1, |index| format!("h1[{index}]"));
+        self.processing_layer
+            .add_to_graph(graph, 2, hidden1, hidden2, |index| format!("h2[{index}]"));
+        self.output_layer
+            .add_to_graph(graph, 3, hidden2, outputs, |index| {
+                NeuronsField::label(OUTPUTS, index)
+            });
+    }
+
+    fn prune<R: Rng>(&mut self, rng: &mut R, pruning: &PruningMutation) {
+        self.input_layer.prune(rng, pruning, false);
+        self.processing_layer.prune(rng, pruning, false);
+        self.output_layer.prune(rng, pruning, true);
+    }
+
+    fn update_structure(&mut self) {
+        self.input_layer.update_structure();
+        self.processing_layer.update_structure();
+        self.output_layer.update_structure();
+    }
+
+    fn reset_learning(&mut self) {
+        self.input_layer.reset_learning();
+        self.processing_layer.reset_learning();
+        self.output_layer.reset_learning();
+    }
+}
+
+impl Neurons {
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        let brain = Brain::Layered(Box::new(LayeredNetwork::random(rng)));
+        Self::with_brain(rng, brain)
+    }
+
+    /// Random network with an evolvable topology.
+    pub fn random_neat<R: Rng>(rng: &mut R, innovations: &mut Innovations) -> Self {
+        let brain = Brain::Neat(NeatNetwork::random(rng, innovations));
+        Self::with_brain(rng, brain)
+    }
+
+    fn with_brain<R: Rng>(rng: &mut R, brain: Brain) -> Self {
+        let mut neurons = Self {
+            inputs: V::zeros(),
+            normalized_inputs: V::zeros(),
+            input_statistics: InputStatistics::new(),
+            outputs: V::zeros(),
+            brain,
+            memory_retention: V::from_fn(|_, _| rng.gen_range(0.0..=1.0)),
+            working_neurons: 0.0,
+        };
+        neurons.update_structure();
+        neurons
+    }
+
+    pub fn brain_type(&self) -> BrainType {
+        match self.brain {
+            Brain::Layered(_) => BrainType::Layered,
+            Brain::Neat(_) => BrainType::Neat,
+        }
+    }
+
+    pub fn num_working_neurons(&self) -> Scalar {
+        self.working_neurons
+    }
+
+    /// The structure of the network, like the number of working neurons, depends on the genes,
+    /// so it needs to be updated every time they change, like after applying a genome.
+    pub(crate) fn update_structure(&mut self) {
+        self.working_neurons = match &mut self.brain {
+            Brain::Layered(network) => {
+                network.update_structure();
+                network.num_working_neurons()
+            }
+            Brain::Neat(network) => network.num_working_neurons(),
+        };
+    }
+
+    /// Disables or restores random connections, neurons and inputs.
+    pub(crate) fn prune<R: Rng>(&mut self, rng: &mut R, pruning: &PruningMutation) {
+        match &mut self.brain {
+            Brain::Layered(network) => network.prune(rng, pruning),
+            Brain::Neat(network) => network.prune(rng, pruning),
+        }
+        self.update_structure();
+    }
+
+    /// Mutates the topology of the network. Only NEAT networks can be mutated.
+    pub(crate) fn mutate_topology<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        innovations: &mut Innovations,
+        mutation: &NeatMutation,
+    ) {
+        if let Brain::Neat(network) = &mut self.brain {
+            network.mutate(rng, innovations, mutation);
+            self.update_structure();
+        }
+    }
+
+    /// Changes the activation function of the hidden neurons.
+    pub(crate) fn mutate_activations<R: Rng>(&mut self, rng: &mut R, probability: Scalar) {
+        let probability = probability.clamp(0.0, 1.0);
+        match &mut self.brain {
+            Brain::Layered(network) => {
+                network.input_layer.mutate_activations(rng, probability);
+                network
+                    .processing_layer
+                    .mutate_activations(rng, probability);
+            }
+            Brain::Neat(network) => network.mutate_activations(rng, probability),
+        }
+    }
+
+    pub fn process(&mut self) {
+        self.prepare_inputs();
+        self.evaluate();
+    }
+
+    /// Evaluates the networks of many cells, whose inputs are already prepared.
+    /// Layered networks with the same weights, like the ones of the cells divided without
+    /// mutations, are grouped, and every layer is evaluated for the whole group with a single
+    /// matrix product, using the weights of the layer as they are.
+    /// The groups are split in batches evaluated in parallel, and the rest of the networks
+    /// are evaluated one by one. The results are the same, up to the rounding of the products.
+    pub(crate) fn evaluate_batch(neurons: &mut [&mut Neurons]) {
+        let mut groups = HashMap::<[u64; 3], Vec<&mut Neurons>>::new();
+        for neurons in neurons.iter_mut() {
+            match &neurons.brain {
+                Brain::Layered(network) => groups.entry(network.fingerprint()).or_default(),
+                Brain::Neat(_) => {
+                    neurons.evaluate();
+                    continue;
+                }
+            }
+            .push(&mut **neurons);
+        }
+        let mut groups: Vec<_> = groups.into_values().collect();
+
+        // Checking the available parallelism is slow, so it is only done for large batches
+        let num_networks: usize = groups.iter().map(Vec::len).sum();
+        let num_threads = if num_networks >= 2 * MIN_BATCH_SIZE {
+            std::thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1)
+                .min(num_networks / MIN_BATCH_SIZE)
+        } else {
+            1
+        };
+        if num_threads == 1 {
+            groups
+                .iter_mut()
+                .for_each(|group| Self::evaluate_group(group));
+            return;
+        }
+        let batch_size = groups.len().div_ceil(num_threads);
+        std::thread::scope(|scope| {
+            for batch in groups.chunks_mut(batch_size) {
+                scope.spawn(move || {
+                    batch
+                        .iter_mut()
+                        .for_each(|group| Self::evaluate_group(group))
+                });
+            }
+        });
+    }
+
+    /// Evaluates layered networks with the same weights, with a column of inputs for every one.
+    fn evaluate_group(group: &mut [&mut Neurons]) {
+        if group.len() <= 1 {
+            group.iter_mut().for_each(|neurons| neurons.evaluate());
+            return;
+        }
+        let inputs = Batch::from_columns(
+            &group
+                .iter()
+                .map(|neurons| neurons.normalized_inputs)
+                .collect::<Vec<_>>(),
+        );
+        let Brain::Layered(network) = &group[0].brain else {
+            group.iter_mut().for_each(|neurons| neurons.evaluate());
+            return;
+        };
+        let (hidden1, hidden2, outputs) = network.process_batch(&inputs);
+        for (index, neurons) in group.iter_mut().enumerate() {
+            if let Brain::Layered(network) = &mut neurons.brain {
+                network.input_layer.outputs = hidden1.column(index).into();
+                network.processing_layer.outputs = hidden2.column(index).into();
+                network.output_layer.outputs = outputs.column(index).into();
+            }
+            neurons.outputs = outputs.column(index).into();
+        }
+    }
+
+    /// Updates the memory and normalizes the inputs set for this step.
+    pub(crate) fn prepare_inputs(&mut self) {
+        // Undefined inputs, like the proportion of molecules in a cell without molecules, are read as zero
+        self.inputs.apply(|x| {
+            if x.is_nan() {
+                *x = 0.0;
+            }
+        });
+        self.update_memory();
+        self.normalize_inputs();
+    }
+
+    /// Feeds the prepared inputs forward through the network.
+    pub(crate) fn evaluate(&mut self) {
+        self.outputs = match &mut self.brain {
+            Brain::Layered(network) => network.process(&self.normalized_inputs),
+            Brain::Neat(network) => network.process(&self.normalized_inputs),
+        };
+    }
+
+    fn normalize_inputs(&mut self) {
+        self.input_statistics.update(&self.inputs);
+        for field in INPUTS {
+            for index in field.range() {
+                let x = self.inputs[index];
+                self.normalized_inputs[index] = match field.normalization {
+                    Normalization::None => x,
+                    Normalization::Static { scale, offset } => (x - offset) * scale,
+                    Normalization::Running => self.input_statistics.standard_score(index, x),
+                };
+            }
+        }
+    }
+
+    /// Inputs fed to the network on the last step, after the normalization.
+    pub fn normalized_inputs(&self) -> &V<NUM_INPUTS> {
+        &self.normalized_inputs
+    }
+
+    pub fn input_fields() -> &'static [InputField] {
+        INPUTS
+    }
+
+    pub fn output_fields() -> &'static [NeuronsField] {
+        OUTPUTS
+    }
+
+    /// Values of the input with the name, or `None` if there is no such input.
+    pub fn input(&self, name: &str) -> Option<&[Scalar]> {
+        let field = INPUTS.iter().find(|field| field.name == name)?;
+        Some(&self.inputs.as_slice()[field.range()])
+    }
+
+    /// Sets the values of the input with the name.
+    /// Returns false if there is no such input, or the number of values doesn't match.
+    pub fn set_input(&mut self, name: &str, values: &[Scalar]) -> bool {
+        match INPUTS.iter().find(|field| field.name == name) {
+            Some(field) if field.len == values.len() => {
+                self.inputs.as_mut_slice()[field.range()].copy_from_slice(values);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Values of the output with the name, or `None` if there is no such output.
+    pub fn output(&self, name: &str) -> Option<&[Scalar]> {
+        let field = OUTPUTS.iter().find(|field| field.name == name)?;
+        Some(&self.outputs.as_slice()[field.range()])
+    }
+
+    /// Sets the values of the output with the name, replacing the ones of the network.
+    /// Returns false if there is no such output, or the number of values doesn't match.
+    pub fn set_output(&mut self, name: &str, values: &[Scalar]) -> bool {
+        match OUTPUTS.iter().find(|field| field.name == name) {
+            Some(field) if field.len == values.len() => {
+                self.outputs.as_mut_slice()[field.range()].copy_from_slice(values);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets the outputs with a controller, instead of evaluating the network.
+    pub(crate) fn control(&mut self, controller: &mut dyn Controller) {
+        self.outputs.fill(0.0);
+        controller.control(self);
+    }
+
+    /// Snapshot of the network as a graph, where the inputs and outputs are named after their fields.
+    pub fn graph(&self) -> NetworkGraph {
+        let mut graph = NetworkGraph::default();
+        graph.nodes.extend((0..NUM_INPUTS).map(|index| GraphNode {
+            id: index,
+            label: NeuronsField::label(INPUTS.iter().map(Deref::deref), index),
+            kind: GraphNodeKind::Input,
+            layer: 0,
+            bias: None,
+            activation: None,
+            value: self.normalized_inputs[index],
+        }));
+        match &self.brain {
+            Brain::Layered(network) => network.add_to_graph(&mut graph),
+            Brain::Neat(network) => Self::add_neat_to_graph(network, &mut graph),
+        }
+        graph
+    }
+
+    /// The hidden nodes are placed after their deepest source, and the outputs after all of them.
+    fn add_neat_to_graph(network: &NeatNetwork<NUM_INPUTS, NUM_OUTPUTS>, graph: &mut NetworkGraph) {
+        let mut sources = HashMap::<NodeId, Vec<NodeId>>::new();
+        for connection in network.connections().values() {
+            sources
+                .entry(connection.to)
+                .or_default()
+                .push(connection.from);
+            graph.edges.push(GraphEdge {
+                from: connection.from,
+                to: connection.to,
+                weight: connection.weight,
+                enabled: connection.enabled,
+            });
+        }
+        let mut layers = HashMap::new();
+        for id in network.nodes().keys() {
+            node_layer(*id, &sources, &mut layers);
+        }
+        let output_layer = network
+            .nodes()
+            .range(NUM_INPUTS + NUM_OUTPUTS..)
+            .map(|(id, _)| layers[id] + 1)
+            .max()
+            .unwrap_or(1);
+        let values = network.node_values();
+        graph.nodes.extend(
+            network
+                .nodes()
+                .iter()
+                .enumerate()
+                .map(|(index, (id, node))| {
+                    let output_index = id
+                        .checked_sub(NUM_INPUTS)
+                        .filter(|index| *index < NUM_OUTPUTS);
+                    let (label, kind, layer) = match output_index {
+                        Some(index) => (
+                            NeuronsField::label(OUTPUTS, index),
+                            GraphNodeKind::Output,
+                            output_layer,
+                        ),
+                        None => (format!("n{id}"), GraphNodeKind::Hidden, layers[id]),
+                    };
+                    GraphNode {
+                        id: *id,
+                        label,
+                        kind,
+                        layer,
+                        bias: Some(node.bias),
+                        activation: Some(node.activation),
+                        value: values.get(index).copied().unwrap_or_default(),
+                    }
+                }),
+        );
+    }
+
+    /// Lifetime learning, modulated by the reward of the last step.
+    /// Only the layered networks are plastic.
+    pub fn learn(&mut self, reward: Scalar) {
+        if let Brain::Layered(network) = &mut self.brain {
+            network.learn(&self.normalized_inputs, reward);
+        }
+    }
+
+    /// Forgets everything learned, so it isn't inherited by the offspring.
+    pub(crate) fn reset_learning(&mut self) {
+        if let Brain::Layered(network) = &mut self.brain {
+            network.reset_learning();
+        }
+    }
+
+    /// Mixes the memory with the memory outputs of the previous step.
+    fn update_memory(&mut self) {
+        let retention = self.memory_retention.map(|x| x.clamp(0.0, 1.0));
+        let memory = retention.component_mul(&self.get_memory_input())
+            + (V::repeat(1.0) - retention).component_mul(&self.get_memory());
+        self.set_memory(&memory);
+    }
+
+    pub fn get_memory_input(&self) -> V<NUM_MEMORY> {
+        self.inputs.fixed_rows::<NUM_MEMORY>(MEMORY_INPUT).into()
+    }
+}
+
+impl BuildGenome for Neurons {
+    fn build_genome(&self, builder: GenomeBuilder) {
+        self.memory_retention
+            .build_genome(builder.nested("memory_retention"));
+        match &self.brain {
+            Brain::Layered(network) => network.build_genome(builder),
+            Brain::Neat(network) => network.build_genome(builder.nested("neat")),
+        }
+    }
+}
+
+impl ApplyGenome for Neurons {
+    fn apply_genome(&mut self, reader: GenomeReader<'_>) {
+        // The kind of network is given by the genes in the genome
+        if reader.contains_nested("neat") {
+            if !matches!(self.brain, Brain::Neat(_)) {
+                self.brain = Brain::Neat(NeatNetwork::empty());
+            }
+        } else if reader.contains_nested("input_layer") && !matches!(self.brain, Brain::Layered(_))
+        {
+            self.brain = Brain::Layered(Box::new(LayeredNetwork::empty()));
+        }
+        self.memory_retention
+            .apply_genome(reader.nested("memory_retention"));
+        match &mut self.brain {
+            Brain::Layered(network) => network.apply_genome(reader),
+            Brain::Neat(network) => network.apply_genome(reader.nested("neat")),
+        }
     }
 }
 
@@ -179,51 +912,102 @@ impl Neurons {
 // (velocity_pos, 2),
 // (acceleration_pos, 2),
 define_inputs!(
-    velocity_magnitude,
-    acceleration_magnitude,
-    radius,
-    age,
-    energy_amount,
-    energy_stored,
-    energy_delta,
+    /// Speed of the cell body.
+    velocity_magnitude => Normalization::scale(1.0 / MAX_SPEED),
+    /// Magnitude of the acceleration of the cell body.
+    acceleration_magnitude => Normalization::Running,
+    /// Current radius of the cell body.
+    radius => Normalization::scale(1.0 / MAX_SIZE),
+    /// Time since the cell was born, in seconds.
+    age => Normalization::scale(AGE_SCALE),
+    /// Available energy.
+    energy_amount => Normalization::scale(1.0 / MAX_ENERGY),
+    /// Energy stored in the molecules.
+    energy_stored => Normalization::Running,
+    /// Change of the available energy since the last step.
+    energy_delta => Normalization::Running,
+    /// Time without energy, relative to the limit before dying.
     zero_energy,
+    /// Energy reserved for the division, relative to the division threshold.
     division_energy_reserve,
+    /// Growth since the last division, relative to the full size, from the newborn ratio up to 1.
     division_grow_factor,
+    /// Proportion of every kind of molecule.
     (molecules_proportion, NUM_MOLECULES),
-    molecules_total,
-    movement_direction,
-    movement_speed,
-    (movement_velocity, 2),
-    movement_velocity_magnitude,
+    /// Total amount of molecules.
+    molecules_total => Normalization::scale(1.0 / (NUM_MOLECULES as Scalar * MAX_MOLECULE_AMOUNT)),
+    /// Direction of the movement, in radians.
+    movement_direction => Normalization::Running,
+    /// Speed of the movement.
+    movement_speed => Normalization::scale(1.0 / MAX_SPEED),
+    /// Velocity of the movement.
+    (movement_velocity, 2) => Normalization::scale(1.0 / MAX_SPEED),
+    /// Magnitude of the velocity of the movement.
+    movement_velocity_magnitude => Normalization::scale(1.0 / MAX_SPEED),
+    /// Energy absorption from the contacts, relative to its limit.
     contact_energy_absorption,
-    contact_count,
+    /// Number of contacts with other bodies.
+    contact_count => Normalization::Running,
+    /// Direction of the contacts.
     (contact_normal, 2),
-    contact_normal_magnitude,
+    /// Magnitude of the sum of the contact normals.
+    contact_normal_magnitude => Normalization::Running,
+    /// Memory cells, mixing their previous value with the memory outputs.
+    (memory, NUM_MEMORY),
 );
 
 define_outputs!(
+    /// Molecules consumed to produce energy when positive, or produced from energy when negative.
     (energy_metabolism, NUM_MOLECULES),
+    /// Energy moved into the division reserve, or out of it when negative.
     division_energy_reserve,
+    /// Contraction of the cell, relative to its limit.
     contraction_amount,
+    /// Change of the direction of the movement.
     movement_angular_speed,
+    /// Speed of the movement, relative to its limit.
     movement_kinetic_speed,
+    /// Change of the energy absorption from the contacts.
     contact_energy_absorption,
+    /// The cell wants to mate when positive.
+    mate,
+    /// Values remembered for the next step.
+    (memory, NUM_MEMORY),
 );
 
 impl std::fmt::Display for Neurons {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Working neurons: {:.0?}", self.working_neurons)?;
         writeln!(f, "I1: {:6.2?}", self.inputs)?;
-        // writeln!(f, "W1: {:.2?}", self.input_layer.weights)?;
-        writeln!(f, "B1: {:6.2?}", self.input_layer.bias)?;
-        writeln!(f, "O1: {:6.2?}", self.input_layer.outputs)?;
-        writeln!(f, "O2: {:.2?}", self.processing_layer.outputs)?;
-        writeln!(f, "O3: {:.2?}", self.output_layer.outputs)?;
-        // writeln!(
-        //     f,
-        //     "activations: {:?}",
-        //     [self.input_layer.activation, self.output_layer.activation]
-        // )?;
+        writeln!(f, "N1: {:6.2?}", self.normalized_inputs)?;
+        match &self.brain {
+            Brain::Layered(network) => {
+                // writeln!(f, "W1: {:.2?}", network.input_layer.weights)?;
+                writeln!(f, "B1: {:6.2?}", network.input_layer.bias)?;
+                writeln!(f, "O1: {:6.2?}", network.input_layer.outputs)?;
+                writeln!(f, "O2: {:.2?}", network.processing_layer.outputs)?;
+                writeln!(f, "O3: {:.2?}", network.output_layer.outputs)?;
+                // writeln!(
+                //     f,
+                //     "activations: {:?}",
+                //     [network.input_layer.activations, network.processing_layer.activations]
+                // )?;
+            }
+            Brain::Neat(network) => {
+                let enabled = network
+                    .connections()
+                    .values()
+                    .filter(|connection| connection.enabled)
+                    .count();
+                writeln!(
+                    f,
+                    "Nodes: {}, connections: {} ({} enabled)",
+                    network.nodes().len(),
+                    network.connections().len(),
+                    enabled
+                )?;
+            }
+        }
         writeln!(
             f,
             "energy_metabolism: {:.2?}",
@@ -245,50 +1029,275 @@ impl std::fmt::Display for Neurons {
             "contact_energy_absorption: {:.2?}",
             self.get_contact_energy_absorption()
         )?;
+        writeln!(f, "mate: {:.2?}", self.get_mate())?;
+        writeln!(
+            f,
+            "memory: {:.2?}, retention: {:.2?}",
+            self.get_memory_input(),
+            self.memory_retention
+        )?;
         Ok(())
     }
 }
 
-#[derive(Clone, BuildGenome)]
+#[derive(Clone, BuildGenome, ApplyGenome)]
 pub struct Layer<const I: usize, const O: usize> {
     /// Every row contains the weights for a given neuron.
     #[build_genome(nested)]
     weights: M<O, I>,
+    /// Whether every connection is enabled, encoded as 1 or 0.
+    #[build_genome(discrete)]
+    enabled: M<O, I>,
     #[build_genome(nested)]
     bias: V<O>,
+    /// Activation function of every neuron.
     #[build_genome(nested)]
-    activation: ActivationFunction,
+    activations: [ActivationFunction; O],
+    #[build_genome(nested)]
+    plasticity: Plasticity,
+    /// Change of the weights learned during the life of the cell. It isn't inherited.
+    learned_weights: M<O, I>,
+    /// Weights with the learned changes, masked by the enable genes.
+    /// Cached for the dense evaluation, and updated when any of them changes.
+    effective_weights: M<O, I>,
+    /// Enabled connections as `(neuron, input)`, only when few enough are enabled.
+    sparse_connections: Option<Vec<(usize, usize)>>,
+    /// Whether every neuron has any enabled connection. The other ones always output zero.
+    working: [bool; O],
     outputs: V<O>,
+    /// Hash of the effective weights, biases and activation functions,
+    /// so the layers with the same one are evaluated together.
+    fingerprint: u64,
 }
 
 impl<const I: usize, const O: usize> Layer<I, O> {
-    pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
-        Self {
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        let mut layer = Self {
             weights: M::from_fn(|_, _| rng.gen_range(-1.0..1.0)),
             bias: V::from_fn(|_, _| rng.gen_range(-1.0..1.0)),
-            activation: ActivationFunction::random(),
+            activations: std::array::from_fn(|_| ActivationFunction::random(rng)),
+            plasticity: Plasticity::random(rng),
+            ..Self::empty()
+        };
+        layer.update_effective_weights();
+        layer
+    }
+
+    /// Layer with all the connections enabled, but without weights.
+    fn empty() -> Self {
+        Self {
+            weights: M::zeros(),
+            enabled: M::repeat(1.0),
+            bias: V::zeros(),
+            activations: [ActivationFunction::Linear; O],
+            plasticity: Plasticity::default(),
+            learned_weights: M::zeros(),
+            effective_weights: M::zeros(),
+            sparse_connections: None,
+            working: [true; O],
             outputs: V::zeros(),
+            fingerprint: 0,
         }
     }
 
     pub fn process(&mut self, input: &V<I>) {
-        let y = self.weights * input + self.bias;
-        self.outputs = self.activation.process(y);
+        let y = match &self.sparse_connections {
+            Some(connections) => {
+                let mut y = self.bias;
+                for (row, col) in connections.iter().copied() {
+                    y[row] +=
+                        (self.weights[(row, col)] + self.learned_weights[(row, col)]) * input[col];
+                }
+                y
+            }
+            None => self.effective_weights * input + self.bias,
+        };
+        self.outputs = V::from_fn(|row, _| self.activate(row, y[row]));
+    }
+
+    fn activate(&self, row: usize, value: Scalar) -> Scalar {
+        if self.working[row] {
+            self.activations[row].apply(value)
+        } else {
+            0.0
+        }
+    }
+
+    /// Same as the dense `process` for many copies of the layer, with a column of `inputs` for
+    /// every copy, so the weights multiply all of them in a single matrix product.
+    fn process_batch(&self, inputs: &Batch<I>) -> Batch<O> {
+        let mut y = self.effective_weights * inputs;
+        for mut column in y.column_iter_mut() {
+            column += self.bias;
+            for (row, value) in column.iter_mut().enumerate() {
+                *value = self.activate(row, *value);
+            }
+        }
+        y
+    }
+
+    /// Updates the learned weights with the ABCD rule, using the input and outputs of the last step.
+    /// The `reward` modulates the learning rate, so it can also be negative.
+    fn learn(&mut self, input: &V<I>, reward: Scalar) {
+        let Plasticity {
+            learning_rate,
+            a,
+            b,
+            c,
+            d,
+        } = self.plasticity;
+        let rate = learning_rate * reward;
+        if rate == 0.0 || !rate.is_finite() {
+            return;
+        }
+        let outputs = &self.outputs;
+        let delta = M::<O, I>::from_fn(|row, col| {
+            let (x, y) = (input[col], outputs[row]);
+            rate * (a * x * y + b * x + c * y + d)
+        });
+        self.learned_weights = (self.learned_weights + delta.component_mul(&self.enabled))
+            .map(|weight| weight.clamp(-MAX_LEARNED_WEIGHT, MAX_LEARNED_WEIGHT));
+        self.update_effective_weights();
+    }
+
+    fn reset_learning(&mut self) {
+        self.learned_weights.fill(0.0);
+        self.update_effective_weights();
+    }
+
+    fn update_effective_weights(&mut self) {
+        self.effective_weights = (self.weights + self.learned_weights).component_mul(&self.enabled);
+        self.update_fingerprint();
+    }
+
+    fn update_fingerprint(&mut self) {
+        let mut hasher = DefaultHasher::new();
+        for value in self.effective_weights.iter().chain(self.bias.iter()) {
+            value.to_bits().hash(&mut hasher);
+        }
+        for activation in self.activations.iter() {
+            (*activation as u8).hash(&mut hasher);
+        }
+        self.working.hash(&mut hasher);
+        self.fingerprint = hasher.finish();
+    }
+
+    fn mutate_activations<R: Rng>(&mut self, rng: &mut R, probability: Scalar) {
+        for activation in self.activations.iter_mut() {
+            if rng.gen_bool(probability) {
+                *activation = ActivationFunction::random(rng);
+            }
+        }
+        self.update_fingerprint();
+    }
+
+    /// Disables random connections, whole neurons or whole inputs, and restores random connections.
+    /// The neurons of the output layer are never disabled.
+    fn prune<R: Rng>(&mut self, rng: &mut R, pruning: &PruningMutation, output_layer: bool) {
+        if rng.gen_bool(pruning.connection.clamp(0.0, 1.0)) {
+            let (row, col) = (rng.gen_range(0..O), rng.gen_range(0..I));
+            self.enabled[(row, col)] = 0.0;
+        }
+        if !output_layer && rng.gen_bool(pruning.neuron.clamp(0.0, 1.0)) {
+            self.enabled.row_mut(rng.gen_range(0..O)).fill(0.0);
+        }
+        if rng.gen_bool(pruning.input.clamp(0.0, 1.0)) {
+            self.enabled.column_mut(rng.gen_range(0..I)).fill(0.0);
+        }
+        if rng.gen_bool(pruning.restore.clamp(0.0, 1.0)) {
+            let (row, col) = (rng.gen_range(0..O), rng.gen_range(0..I));
+            self.enabled[(row, col)] = 1.0;
+        }
     }
 
     pub fn outputs(&self) -> &V<O> {
         &self.outputs
     }
 
+    /// Adds the neurons of the layer, and the connections from the neurons of the previous one.
+    /// The weights include what was learned during the life of the cell.
+    fn add_to_graph(
+        &self,
+        graph: &mut NetworkGraph,
+        layer: usize,
+        first_input: usize,
+        first_id: usize,
+        label: impl Fn(usize) -> String,
+    ) {
+        let kind = if layer == 3 {
+            GraphNodeKind::Output
+        } else {
+            GraphNodeKind::Hidden
+        };
+        for row in 0..O {
+            graph.nodes.push(GraphNode {
+                id: first_id + row,
+                label: label(row),
+                kind,
+                layer,
+                bias: Some(self.bias[row]),
+                activation: Some(self.activations[row]),
+                value: self.outputs[row],
+            });
+            for col in 0..I {
+                graph.edges.push(GraphEdge {
+                    from: first_input + col,
+                    to: first_id + row,
+                    weight: self.weights[(row, col)] + self.learned_weights[(row, col)],
+                    enabled: self.enabled[(row, col)] > 0.0,
+                });
+            }
+        }
+    }
+
+    /// Normalizes the enable genes, and chooses the sparse evaluation when few connections are enabled.
+    fn update_structure(&mut self) {
+        self.enabled
+            .apply(|x| *x = if *x >= 0.5 { 1.0 } else { 0.0 });
+        self.working = std::array::from_fn(|row| self.enabled.row(row).sum() > 0.0);
+        self.update_effective_weights();
+        let num_enabled = self.enabled.sum() as usize;
+        self.sparse_connections = (num_enabled as Scalar <= SPARSE_DENSITY * (O * I) as Scalar)
+            .then(|| {
+                (0..O)
+                    .flat_map(|row| (0..I).map(move |col| (row, col)))
+                    .filter(|index| self.enabled[*index] > 0.0)
+                    .collect()
+            });
+    }
+
+    /// Neurons with at least one enabled connection, as the other ones always output zero.
     fn num_working_neurons(&self) -> Scalar {
-        let active = self
-            .weights
-            .apply_into(|x| *x = if x.abs() > 0.0 { 1.0 } else { 0.0 });
-        active
-            .row_sum()
-            .apply(|x| *x = if x.abs() > 0.0 { 1.0 } else { 0.0 });
-        active.sum()
+        self.working.iter().filter(|working| **working).count() as Scalar
+    }
+}
+
+/// Coefficients of the ABCD rule, a generalization of the Hebbian learning:
+/// `Δw = learning_rate * reward * (a * x * y + b * x + c * y + d)`,
+/// where `x` is the input of the connection and `y` the output of the neuron.
+#[derive(Clone, Copy, Debug, Default, BuildGenome, ApplyGenome)]
+pub struct Plasticity {
+    #[build_genome(gen)]
+    learning_rate: Scalar,
+    #[build_genome(gen)]
+    a: Scalar,
+    #[build_genome(gen)]
+    b: Scalar,
+    #[build_genome(gen)]
+    c: Scalar,
+    #[build_genome(gen)]
+    d: Scalar,
+}
+
+impl Plasticity {
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        Self {
+            learning_rate: rng.gen_range(0.0..=MAX_LEARNING_RATE),
+            a: rng.gen_range(-1.0..1.0),
+            b: rng.gen_range(-1.0..1.0),
+            c: rng.gen_range(-1.0..1.0),
+            d: rng.gen_range(-1.0..1.0),
+        }
     }
 }
 
@@ -299,46 +1308,89 @@ pub enum ActivationFunction {
     Tanh,
     Relu,
     Swish,
+    Gaussian,
+    Sine,
+    Step,
+    Abs,
 }
 
 impl ActivationFunction {
-    pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
         let choices = [
             Self::Linear,
             Self::Sigmoid,
             // Self::Tanh,
             Self::Relu,
             Self::Swish,
+            Self::Gaussian,
+            Self::Sine,
+            Self::Step,
+            Self::Abs,
         ];
-        choices.choose(&mut rng).unwrap().clone()
+        *choices.choose(rng).unwrap()
     }
 
     pub fn process<const N: usize>(&self, input: V<N>) -> V<N> {
         match self {
             Self::Linear => input,
-            Self::Sigmoid => input.apply_into(|x| *x = 1.0 / (1.0 + (-*x).exp())),
-            Self::Tanh => input.apply_into(|x| {
-                let a = x.exp();
-                let b = (-*x).exp();
-                *x = (a - b) / (a + b);
-            }),
-            Self::Relu => input.apply_into(|x| *x = x.max(0.0)),
-            Self::Swish => input.apply_into(|x| *x = *x / (1.0 + (-*x).exp())),
+            _ => input.apply_into(|x| *x = self.apply(*x)),
+        }
+    }
+
+    pub fn apply(&self, x: Scalar) -> Scalar {
+        match self {
+            Self::Linear => x,
+            Self::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Self::Tanh => x.tanh(),
+            Self::Relu => x.max(0.0),
+            Self::Swish => x / (1.0 + (-x).exp()),
+            Self::Gaussian => (-x * x).exp(),
+            Self::Sine => x.sin(),
+            Self::Step => {
+                if x > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Abs => x.abs(),
         }
     }
 }
 
 impl BuildGenome for ActivationFunction {
-    fn build_genome<'a>(&self, builder: GenomeBuilder) {
+    fn build_genome(&self, builder: GenomeBuilder) {
         let value = match self {
             ActivationFunction::Linear => 1.0,
             ActivationFunction::Sigmoid => 2.0,
             ActivationFunction::Tanh => 3.0,
             ActivationFunction::Relu => 4.0,
             ActivationFunction::Swish => 5.0,
+            ActivationFunction::Gaussian => 6.0,
+            ActivationFunction::Sine => 7.0,
+            ActivationFunction::Step => 8.0,
+            ActivationFunction::Abs => 9.0,
         };
-        builder.add("activation_function", Gen { value: value });
+        builder.add("activation_function", Gen::discrete(value));
+    }
+}
+
+impl ApplyGenome for ActivationFunction {
+    fn apply_genome(&mut self, reader: GenomeReader<'_>) {
+        if let Some(value) = reader.get("activation_function") {
+            *self = match value.round() as i64 {
+                1 => ActivationFunction::Linear,
+                2 => ActivationFunction::Sigmoid,
+                3 => ActivationFunction::Tanh,
+                4 => ActivationFunction::Relu,
+                5 => ActivationFunction::Swish,
+                6 => ActivationFunction::Gaussian,
+                7 => ActivationFunction::Sine,
+                8 => ActivationFunction::Step,
+                9 => ActivationFunction::Abs,
+                _ => *self,
+            };
+        }
     }
 }
 
@@ -350,7 +1402,266 @@ impl std::fmt::Debug for ActivationFunction {
             Self::Tanh => "tanh",
             Self::Relu => "relu",
             Self::Swish => "swish",
+            Self::Gaussian => "gaussian",
+            Self::Sine => "sine",
+            Self::Step => "step",
+            Self::Abs => "abs",
         };
         f.write_str(name)
     }
 }
+
+/// Depth of the node, following the sources. Cycles are cut at the first repeated node.
+fn node_layer(
+    node: NodeId,
+    sources: &HashMap<NodeId, Vec<NodeId>>,
+    layers: &mut HashMap<NodeId, usize>,
+) -> usize {
+    if node < NUM_INPUTS {
+        return 0;
+    }
+    if let Some(layer) = layers.get(&node) {
+        return *layer;
+    }
+    layers.insert(node, 1);
+    let layer = sources
+        .get(&node)
+        .into_iter()
+        .flatten()
+        .map(|source| node_layer(*source, sources, layers) + 1)
+        .max()
+        .unwrap_or(1);
+    layers.insert(node, layer);
+    layer
+}
+
+impl<const N: usize> BuildGenome for [ActivationFunction; N] {
+    fn build_genome(&self, builder: GenomeBuilder) {
+        for (index, activation) in self.iter().enumerate() {
+            activation.build_genome(builder.nested(&format!("{index:03}")));
+        }
+    }
+}
+
+impl<const N: usize> ApplyGenome for [ActivationFunction; N] {
+    fn apply_genome(&mut self, reader: GenomeReader<'_>) {
+        for (index, activation) in self.iter_mut().enumerate() {
+            activation.apply_genome(reader.nested(&format!("{index:03}")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genome::Genome;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn genome(neurons: &Neurons) -> Genome {
+        let builder = GenomeBuilder::new();
+        neurons.build_genome(builder.clone());
+        builder.build()
+    }
+
+    fn normalized_input(neurons: &Neurons, name: &str) -> Scalar {
+        let field = INPUTS.iter().find(|field| field.name == name).unwrap();
+        neurons.normalized_inputs[field.start]
+    }
+
+    #[test]
+    fn static_normalization_scales_the_inputs() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut neurons = Neurons::random(&mut rng);
+        neurons.set_input("age", &[120.0]);
+        neurons.set_input("contact_energy_absorption", &[3.0]);
+        neurons.normalize_inputs();
+
+        assert_eq!(normalized_input(&neurons, "age"), 120.0 * AGE_SCALE);
+        assert_eq!(normalized_input(&neurons, "contact_energy_absorption"), 3.0);
+    }
+
+    #[test]
+    fn running_normalization_uses_the_standard_score() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut neurons = Neurons::random(&mut rng);
+        // Without variance yet, the score is zero
+        neurons.set_input("energy_delta", &[1.0]);
+        neurons.normalize_inputs();
+        assert_eq!(normalized_input(&neurons, "energy_delta"), 0.0);
+
+        // The mean is 2 and the variance 1
+        neurons.set_input("energy_delta", &[3.0]);
+        neurons.normalize_inputs();
+        assert_eq!(normalized_input(&neurons, "energy_delta"), 1.0);
+
+        let index = INPUTS
+            .iter()
+            .find(|field| field.name == "energy_delta")
+            .unwrap()
+            .start;
+        assert_eq!(
+            neurons.input_statistics.standard_score(index, 1e6),
+            MAX_NORMALIZED_VALUE
+        );
+    }
+
+    #[test]
+    fn memory_outputs_are_fed_back_on_the_next_step() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut neurons = Neurons::random(&mut rng);
+        neurons.memory_retention = V::zeros();
+        neurons.process();
+        let memory = neurons.get_memory().clone_owned();
+        neurons.process();
+        assert_eq!(neurons.get_memory_input(), memory);
+
+        // The retention keeps part of the previous memory
+        neurons.memory_retention = V::repeat(0.75);
+        let outputs = neurons.get_memory().clone_owned();
+        neurons.process();
+        let expected = 0.75 * memory + 0.25 * outputs;
+        assert!((neurons.get_memory_input() - expected).amax() < 1e-12);
+    }
+
+    #[test]
+    fn hebbian_learning_follows_the_abcd_rule() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut layer = Layer::<2, 2>::random(&mut rng);
+        layer.plasticity = Plasticity {
+            learning_rate: 0.1,
+            a: 0.5,
+            b: -0.25,
+            c: 0.125,
+            d: 0.0625,
+        };
+        layer.enabled[(1, 0)] = 0.0;
+        layer.update_structure();
+        let input = V::<2>::new(0.5, -1.0);
+        layer.process(&input);
+        let outputs = layer.outputs;
+        let weights = layer.effective_weights;
+
+        layer.learn(&input, 2.0);
+        for (row, col) in [(0, 0), (0, 1), (1, 1)] {
+            let (x, y) = (input[col], outputs[row]);
+            let delta = 0.2 * (0.5 * x * y - 0.25 * x + 0.125 * y + 0.0625);
+            assert!((layer.learned_weights[(row, col)] - delta).abs() < 1e-12);
+            let weight = weights[(row, col)] + delta;
+            assert!((layer.effective_weights[(row, col)] - weight).abs() < 1e-12);
+        }
+        assert_eq!(layer.learned_weights[(1, 0)], 0.0);
+        assert_eq!(layer.effective_weights[(1, 0)], 0.0);
+
+        // The learned weights are bounded
+        layer.learn(&input, 1e6);
+        assert!(layer.learned_weights.amax() <= MAX_LEARNED_WEIGHT);
+    }
+
+    #[test]
+    fn sparse_and_dense_evaluations_are_equal() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sparse = Layer::<8, 4>::random(&mut rng);
+        // A quarter of the connections, and none of the last neuron
+        sparse.enabled = M::from_fn(|row, col| {
+            if (row + col) % 4 == 0 && row != 3 {
+                1.0
+            } else {
+                0.0
+            }
+        });
+        sparse.update_structure();
+        assert!(sparse.sparse_connections.is_some());
+        sparse.plasticity.learning_rate = MAX_LEARNING_RATE;
+        let mut dense = sparse.clone();
+        dense.sparse_connections = None;
+
+        for _ in 0..5 {
+            let input = V::<8>::from_fn(|_, _| rng.gen_range(-2.0..2.0));
+            sparse.process(&input);
+            dense.process(&input);
+            assert!((sparse.outputs - dense.outputs).amax() < 1e-12);
+            sparse.learn(&input, 1.0);
+            dense.learn(&input, 1.0);
+        }
+    }
+
+    #[test]
+    fn default_mutations_keep_the_network() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut mutation = NeuronsMutation::default();
+        let neurons = Neurons::random(&mut rng);
+        let mut child = neurons.clone();
+
+        mutation.apply(&mut rng, &mut child);
+
+        assert_eq!(genome(&neurons).distance(&genome(&child)), 0.0);
+    }
+
+    #[test]
+    fn disabled_neurons_output_zero_and_are_not_charged() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut neurons = Neurons::random(&mut rng);
+        let working = neurons.num_working_neurons();
+        if let Brain::Layered(network) = &mut neurons.brain {
+            network.input_layer.enabled.row_mut(0).fill(0.0);
+            network.input_layer.bias[0] = 1.0;
+            network.input_layer.activations = [ActivationFunction::Sigmoid; NUM_PROCESSING];
+        }
+        neurons.update_structure();
+        assert_eq!(neurons.num_working_neurons(), working - 1.0);
+
+        neurons.inputs = V::from_fn(|_, _| rng.gen_range(-2.0..2.0));
+        neurons.process();
+        let Brain::Layered(network) = &neurons.brain else {
+            unreachable!()
+        };
+        assert_eq!(network.input_layer.outputs[0], 0.0);
+        assert!(network.input_layer.outputs[1] != 0.0);
+    }
+
+    #[test]
+    fn batched_and_single_evaluations_are_equal() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut innovations = Innovations::new();
+        let mut networks: Vec<_> = (0..4).map(|_| Neurons::random(&mut rng)).collect();
+        networks.push(Neurons::random_neat(&mut rng, &mut innovations));
+        let mut sparse = Neurons::random(&mut rng);
+        if let Brain::Layered(network) = &mut sparse.brain {
+            network.processing_layer.enabled.fill(0.0);
+        }
+        sparse.update_structure();
+        networks.push(sparse);
+        for neurons in networks.iter_mut() {
+            neurons.inputs = V::from_fn(|_, _| rng.gen_range(-2.0..2.0));
+            neurons.prepare_inputs();
+            // Learn something, so the learned weights are also used
+            neurons.evaluate();
+            neurons.learn(1.0);
+        }
+        // Copies of the same network are evaluated together, with a matrix product
+        let copies = vec![networks[0].clone(); 8];
+        networks.extend(copies);
+        for neurons in networks.iter_mut() {
+            neurons.inputs = V::from_fn(|_, _| rng.gen_range(-2.0..2.0));
+            neurons.prepare_inputs();
+        }
+
+        let mut batched = networks.clone();
+        networks.iter_mut().for_each(|neurons| neurons.evaluate());
+        Neurons::evaluate_batch(&mut batched.iter_mut().collect::<Vec<_>>());
+
+        for (single, batched) in networks.iter().zip(batched.iter()) {
+            assert!((single.outputs - batched.outputs).amax() < 1e-12);
+            assert_eq!(single.graph().nodes.len(), batched.graph().nodes.len());
+            for (node1, node2) in single
+                .graph()
+                .nodes
+                .iter()
+                .zip(batched.graph().nodes.iter())
+            {
+                assert!((node1.value - node2.value).abs() < 1e-12);
+            }
+        }
+    }
+}
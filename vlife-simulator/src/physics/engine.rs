@@ -50,7 +50,7 @@ impl Physics {
         }
     }
 
-    pub fn objects(&self) -> Objects {
+    pub fn objects(&self) -> Objects<'_> {
         Objects(self.objects.iter())
     }
 
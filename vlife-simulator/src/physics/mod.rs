@@ -1,5 +1,5 @@
 mod engine;
 mod object;
 
-pub use engine::{Contact, ObjectId, Objects, Physics};
+pub use engine::{Contact, ObjectId, Physics};
 pub use object::Object;
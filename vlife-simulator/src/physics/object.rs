@@ -43,6 +43,13 @@ impl Object {
         self.position
     }
 
+    /// Moves the object to the position, stopping it.
+    pub fn set_position(&mut self, position: Vec2) {
+        self.position = position;
+        self.last_position = position;
+        self.velocity = zero();
+    }
+
     pub fn velocity(&self) -> Vec2 {
         self.velocity
     }
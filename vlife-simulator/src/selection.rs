@@ -0,0 +1,169 @@
+use rand::Rng;
+
+use crate::Scalar;
+
+/// How the cells of the rank are chosen as parents for the reseeded cells.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SelectionStrategy {
+    /// All the cells in the rank have the same probability.
+    #[default]
+    Uniform,
+    /// The best of `size` cells chosen uniformly.
+    Tournament { size: usize },
+    /// The probability is proportional to the fitness score, so cells without a positive score
+    /// are never chosen, unless no score is positive and all the cells have the same probability.
+    Roulette,
+    /// The probability grows linearly with the position in the rank.
+    /// The `pressure` goes from 1.0 (uniform) to 2.0 (the worst cell is never chosen).
+    RankLinear { pressure: Scalar },
+    /// Only the best `fraction` of the cells can be chosen, all of them with the same probability.
+    Truncation { fraction: Scalar },
+}
+
+impl SelectionStrategy {
+    /// Chooses an index from the scores, which must be sorted in ascending order.
+    pub(crate) fn choose<R: Rng>(&self, rng: &mut R, scores: &[Scalar]) -> Option<usize> {
+        let len = scores.len();
+        if len == 0 {
+            return None;
+        }
+        match self {
+            Self::Uniform => Some(rng.gen_range(0..len)),
+            Self::Tournament { size } => (0..(*size).max(1)).map(|_| rng.gen_range(0..len)).max(),
+            Self::Roulette => {
+                let weights = scores.iter().map(|score| score.max(0.0));
+                Self::choose_weighted(rng, weights).or_else(|| Some(rng.gen_range(0..len)))
+            }
+            Self::RankLinear { pressure } => {
+                let pressure = pressure.clamp(1.0, 2.0);
+                let n = len as Scalar;
+                let weights = (0..len).map(|rank| {
+                    let rank = rank as Scalar;
+                    (2.0 - pressure) / n + 2.0 * rank * (pressure - 1.0) / (n * (n - 1.0).max(1.0))
+                });
+                // With a single cell and the maximum pressure all the weights are zero
+                Self::choose_weighted(rng, weights).or(Some(len - 1))
+            }
+            Self::Truncation { fraction } => {
+                let selected = ((fraction.clamp(0.0, 1.0) * len as Scalar).ceil() as usize).max(1);
+                Some(rng.gen_range(len - selected..len))
+            }
+        }
+    }
+
+    fn choose_weighted<R: Rng>(
+        rng: &mut R,
+        weights: impl Iterator<Item = Scalar> + Clone,
+    ) -> Option<usize> {
+        let total = weights.clone().sum::<Scalar>();
+        if total > 0.0 {
+            let mut target = rng.gen_range(0.0..total);
+            let mut last = None;
+            for (index, weight) in weights.enumerate() {
+                if weight > 0.0 {
+                    if target < weight {
+                        return Some(index);
+                    }
+                    target -= weight;
+                    last = Some(index);
+                }
+            }
+            last
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    const STRATEGIES: [SelectionStrategy; 5] = [
+        SelectionStrategy::Uniform,
+        SelectionStrategy::Tournament { size: 3 },
+        SelectionStrategy::Roulette,
+        SelectionStrategy::RankLinear { pressure: 2.0 },
+        SelectionStrategy::Truncation { fraction: 0.2 },
+    ];
+
+    fn count_choices(strategy: SelectionStrategy, scores: &[Scalar]) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = vec![0; scores.len()];
+        for _ in 0..1000 {
+            counts[strategy.choose(&mut rng, scores).unwrap()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn no_candidates() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for strategy in STRATEGIES {
+            assert_eq!(strategy.choose(&mut rng, &[]), None);
+        }
+    }
+
+    #[test]
+    fn single_candidate() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for strategy in STRATEGIES {
+            for score in [-1.0, 0.0, 1.0] {
+                assert_eq!(strategy.choose(&mut rng, &[score]), Some(0));
+            }
+        }
+    }
+
+    #[test]
+    fn roulette_never_chooses_scores_without_positive_value() {
+        let counts = count_choices(SelectionStrategy::Roulette, &[-2.0, 0.0, 1.0, 3.0]);
+        assert_eq!(counts[0], 0);
+        assert_eq!(counts[1], 0);
+        assert!(counts[3] > counts[2]);
+    }
+
+    #[test]
+    fn roulette_is_uniform_without_positive_scores() {
+        let counts = count_choices(SelectionStrategy::Roulette, &[-2.0, -1.0, 0.0]);
+        assert!(counts.iter().all(|count| *count > 250));
+    }
+
+    #[test]
+    fn tournament_without_size_chooses_any_candidate() {
+        let counts = count_choices(
+            SelectionStrategy::Tournament { size: 0 },
+            &[-2.0, -1.0, 0.0],
+        );
+        assert!(counts.iter().all(|count| *count > 250));
+    }
+
+    #[test]
+    fn tournament_prefers_the_best_candidates() {
+        let counts = count_choices(
+            SelectionStrategy::Tournament { size: 3 },
+            &[-2.0, -1.0, 0.0],
+        );
+        assert!(counts[0] < counts[1] && counts[1] < counts[2]);
+    }
+
+    #[test]
+    fn rank_linear_with_maximum_pressure_never_chooses_the_worst() {
+        let counts = count_choices(
+            SelectionStrategy::RankLinear { pressure: 2.0 },
+            &[0.0, 1.0, 2.0],
+        );
+        assert_eq!(counts[0], 0);
+        assert!(counts[2] > counts[1]);
+    }
+
+    #[test]
+    fn truncation_keeps_at_least_the_best() {
+        let counts = count_choices(
+            SelectionStrategy::Truncation { fraction: 0.0 },
+            &[0.0, 1.0, 2.0],
+        );
+        assert_eq!(counts, vec![0, 0, 1000]);
+    }
+}
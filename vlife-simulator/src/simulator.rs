@@ -1,16 +1,33 @@
 use indexmap::{map::Iter, IndexMap};
 use num_traits::{float::FloatConst, Zero};
-use rand::{prelude::ThreadRng, Rng};
-use std::{collections::HashMap, fmt::Display, ops::Deref};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    ops::Deref,
+};
 
-use crate::cell::{Cell, MAX_SIZE};
-use crate::cell_rank::CellRank;
-use crate::genome::Genome;
+use crate::cell::{Cell, MAX_SIZE, NUM_MOLECULES};
+use crate::cell_rank::{CellRank, RankAging};
+use crate::controller::Controller;
+use crate::events::{DeathCause, Event, EventKind, ReseedSource};
+use crate::fitness::{EnergyRatioFitness, FitnessFunction};
+use crate::genome::{CrossoverOperator, Genome};
+use crate::lineage::{Lineage, Phylogeny};
+use crate::neat::{Innovations, NeatMutation};
+use crate::neurons::{BrainType, Neurons, NeuronsMutation, PruningMutation};
 use crate::physics::{Contact, Object, ObjectId, Physics};
-use crate::{Scalar, Vec2};
+use crate::selection::SelectionStrategy;
+use crate::species::{Speciation, Species};
+use crate::statistics::Statistics;
+use crate::{Scalar, Vec2, V};
 
 pub const RANK_SIZE: usize = 100;
 
+/// Maximum number of events kept until drained. The oldest ones are discarded first.
+pub const MAX_EVENTS: usize = 100_000;
+
 pub type CellId = usize;
 
 pub struct Simulator {
@@ -19,11 +36,26 @@ pub struct Simulator {
     cells: IndexMap<CellId, Cell>,
     physics: Physics,
     time: Scalar,
-    dead_cells: Vec<CellId>,
-    born_cells: Vec<Cell>,
+    dead_cells: Vec<(CellId, DeathCause)>,
+    born_cells: Vec<(Birth, Cell)>,
+    mating_cells: Vec<(CellId, CellId)>,
     object_cell: HashMap<ObjectId, CellId>,
     min_cells: usize,
     rank: CellRank,
+    fitness_function: Box<dyn FitnessFunction>,
+    crossover_operator: CrossoverOperator,
+    brain_type: BrainType,
+    neurons_mutation: NeuronsMutation,
+    plasticity: bool,
+    batch_evaluation: bool,
+    /// Cells whose neuronal network is replaced by a controller.
+    controllers: HashMap<CellId, Box<dyn Controller>>,
+    events: VecDeque<Event>,
+    phylogeny: Phylogeny,
+    statistics: Statistics,
+    speciation: Speciation,
+    /// Every simulator has its own random generator, so they don't interfere with each other.
+    rng: StdRng,
 }
 
 impl Simulator {
@@ -36,9 +68,22 @@ impl Simulator {
             time: 0.0,
             dead_cells: Vec::new(),
             born_cells: Vec::new(),
+            mating_cells: Vec::new(),
             object_cell: HashMap::new(),
             min_cells: 0,
             rank: CellRank::new(RANK_SIZE),
+            fitness_function: Box::new(EnergyRatioFitness),
+            crossover_operator: CrossoverOperator::default(),
+            brain_type: BrainType::default(),
+            neurons_mutation: NeuronsMutation::default(),
+            plasticity: false,
+            batch_evaluation: false,
+            controllers: HashMap::new(),
+            events: VecDeque::new(),
+            phylogeny: Phylogeny::new(),
+            statistics: Statistics::default(),
+            speciation: Speciation::default(),
+            rng: StdRng::from_entropy(),
         }
     }
 
@@ -47,58 +92,292 @@ impl Simulator {
         self
     }
 
+    /// Seeds the random generator of the simulation, so it can be reproduced.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// The function used to score the dead cells before inserting them into the rank.
+    pub fn with_fitness_function(mut self, function: impl FitnessFunction + 'static) -> Self {
+        self.fitness_function = Box::new(function);
+        self
+    }
+
+    /// How the genomes of the parents are combined, both for mating and reseeding.
+    pub fn with_crossover_operator(mut self, operator: CrossoverOperator) -> Self {
+        self.crossover_operator = operator;
+        self
+    }
+
+    /// Kind of neuronal network of the random cells.
+    pub fn with_brain_type(mut self, brain_type: BrainType) -> Self {
+        self.brain_type = brain_type;
+        self
+    }
+
+    /// Mutations applied to the NEAT networks of the newborn and recombined cells.
+    pub fn with_neat_mutation(mut self, mutation: NeatMutation) -> Self {
+        self.neurons_mutation.neat = mutation;
+        self
+    }
+
+    /// Probability of changing the activation function of every hidden neuron
+    /// of the newborn and recombined cells. It is zero by default.
+    pub fn with_activation_mutation(mut self, probability: Scalar) -> Self {
+        self.neurons_mutation.activation = probability;
+        self
+    }
+
+    /// Mutations that disable or restore parts of the networks of the newborn and recombined cells.
+    /// They are disabled by default.
+    pub fn with_pruning_mutation(mut self, pruning: PruningMutation) -> Self {
+        self.neurons_mutation.pruning = pruning;
+        self
+    }
+
+    /// Whether the cells learn during their life, using the energy delta as reward.
+    pub fn with_plasticity(mut self, enabled: bool) -> Self {
+        self.plasticity = enabled;
+        self
+    }
+
+    /// Whether the cells whose neuronal networks have the same weights are evaluated together,
+    /// with a matrix product for every layer, in parallel batches, instead of one by one.
+    /// The results are the same, up to rounding. It is disabled by default, as it is only faster
+    /// when many cells share their weights, as measured by the `batch_evaluation` example.
+    pub fn with_batch_evaluation(mut self, enabled: bool) -> Self {
+        self.batch_evaluation = enabled;
+        self
+    }
+
+    /// How the parents of the reseeded cells are chosen from the rank.
+    pub fn with_selection_strategy(mut self, strategy: SelectionStrategy) -> Self {
+        self.rank.set_selection_strategy(strategy);
+        self
+    }
+
+    /// How the entries of the rank get old.
+    pub fn with_rank_aging(mut self, aging: RankAging) -> Self {
+        self.rank.set_aging(aging);
+        self
+    }
+
+    /// Number of best entries of the rank that are reinserted unchanged before using recombination.
+    pub fn with_elite_size(mut self, elite_size: usize) -> Self {
+        self.rank.set_elite_size(elite_size);
+        self
+    }
+
+    /// Maximum genome distance between the cells of a species,
+    /// and number of simulation steps between the clustering of the cells into species.
+    pub fn with_speciation(mut self, threshold: Scalar, interval: usize) -> Self {
+        self.speciation = Speciation::new(threshold, interval);
+        self
+    }
+
+    /// Number of simulation steps between statistics samples.
+    pub fn with_statistics_interval(mut self, steps: usize) -> Self {
+        self.statistics = Statistics::new(steps);
+        self
+    }
+
+    pub fn rank(&self) -> &CellRank {
+        &self.rank
+    }
+
+    pub fn rank_mut(&mut self) -> &mut CellRank {
+        &mut self.rank
+    }
+
+    pub fn selection_strategy(&self) -> SelectionStrategy {
+        self.rank.selection_strategy()
+    }
+
+    pub fn set_selection_strategy(&mut self, strategy: SelectionStrategy) {
+        self.rank.set_selection_strategy(strategy);
+    }
+
+    pub fn brain_type(&self) -> BrainType {
+        self.brain_type
+    }
+
+    pub fn innovations(&self) -> &Innovations {
+        &self.neurons_mutation.innovations
+    }
+
     pub fn time(&self) -> Scalar {
         self.time
     }
 
+    pub fn phylogeny(&self) -> &Phylogeny {
+        &self.phylogeny
+    }
+
+    pub fn phylogeny_mut(&mut self) -> &mut Phylogeny {
+        &mut self.phylogeny
+    }
+
+    pub fn speciation(&self) -> &Speciation {
+        &self.speciation
+    }
+
+    pub fn get_cell_species(&self, cell_id: CellId) -> Option<&Species> {
+        self.speciation
+            .species_of(cell_id)
+            .and_then(|species_id| self.speciation.get(species_id))
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    pub fn statistics_mut(&mut self) -> &mut Statistics {
+        &mut self.statistics
+    }
+
+    /// The events generated since they were last drained, from the oldest to the newest.
+    pub fn events(&self) -> impl Iterator<Item = &Event> + '_ {
+        self.events.iter()
+    }
+
+    /// Takes the events generated since they were last drained.
+    /// Only the last [`MAX_EVENTS`] are kept, so they need to be drained regularly.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.events.drain(..)
+    }
+
     pub fn add_testing_cell(&mut self) -> CellId {
         let position = Vec2::new(20.0, 200.0);
         let radius = 10.0;
         let object_id = self.physics.add_object(position, radius);
-        let cell_id = self.next_cell_id;
-        self.next_cell_id += 1;
-        let mut cell = Cell::random(object_id, radius);
+        let mut cell = Cell::random(&mut self.rng, object_id, radius);
         cell.molecules.set_zero();
         cell.energy = 10000.0;
         cell.movement_speed_limit = 10.0;
         cell.movement_direction = 0.20 * Scalar::PI();
         cell.movement_speed = 10.0;
-        self.cells.insert(cell_id, cell);
-        self.object_cell.insert(object_id, cell_id);
+        let cell_id = self.insert_cell(cell, &[]);
+        self.add_event(EventKind::Added {
+            cell_id,
+            parent_id: None,
+        });
+        cell_id
+    }
+
+    fn add_cell(&mut self, genome: Genome, parents: &[Lineage]) -> CellId {
+        let position = self.find_free_position(Self::genome_radius(&genome));
+        self.add_cell_at(&genome, position, parents)
+    }
+
+    fn add_cell_at(&mut self, genome: &Genome, position: Vec2, parents: &[Lineage]) -> CellId {
+        let object_id = self
+            .physics
+            .add_object(position, Self::genome_radius(genome));
+        let cell = Cell::from_genome(&mut self.rng, object_id, genome);
+        self.insert_cell(cell, parents)
+    }
+
+    fn genome_radius(genome: &Genome) -> Scalar {
+        genome
+            .reader()
+            .get("size")
+            .unwrap_or(MAX_SIZE)
+            .clamp(1.0, MAX_SIZE)
+    }
+
+    /// Adds a cell with the genome at the position, descending from the parent when given,
+    /// like a clone with some edited genes.
+    pub fn add_cell_with_genome(
+        &mut self,
+        genome: &Genome,
+        position: Vec2,
+        parent: Option<CellId>,
+    ) -> CellId {
+        let parents: Vec<_> = parent
+            .and_then(|parent| self.cells.get(&parent))
+            .map(|parent| parent.lineage.clone())
+            .into_iter()
+            .collect();
+        let cell_id = self.add_cell_at(genome, position, &parents);
+        self.add_event(EventKind::Added {
+            cell_id,
+            parent_id: parents.first().map(|parent| parent.cell_id),
+        });
         cell_id
     }
 
-    fn add_cell(&mut self, _genome: Genome) {
-        // Cell::new(genome);
-        todo!()
+    /// Applies the genome to a living cell, keeping its state like the energy and the age.
+    /// Returns false if there is no such cell.
+    pub fn set_cell_genome(&mut self, cell_id: CellId, genome: &Genome) -> bool {
+        match self.cells.get_mut(&cell_id) {
+            Some(cell) => {
+                cell.set_genome(genome);
+                self.speciation.on_genome_changed(cell_id);
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn add_random_cell(&mut self) -> CellId {
-        let mut rng = rand::thread_rng();
+        let cell_id = self.add_random_cell_anywhere();
+        self.add_event(EventKind::Added {
+            cell_id,
+            parent_id: None,
+        });
+        cell_id
+    }
 
-        let radius = rng.gen_range(1.0..=MAX_SIZE);
-        let position = self.find_free_position(&mut rng, radius);
+    pub fn add_random_cell_at(&mut self, position: Vec2) -> CellId {
+        let radius = self.rng.gen_range(1.0..=MAX_SIZE);
+        let cell_id = self.add_random_cell_with(position, radius);
+        self.add_event(EventKind::Added {
+            cell_id,
+            parent_id: None,
+        });
+        cell_id
+    }
+
+    fn add_random_cell_anywhere(&mut self) -> CellId {
+        let radius = self.rng.gen_range(1.0..=MAX_SIZE);
+        let position = self.find_free_position(radius);
+        self.add_random_cell_with(position, radius)
+    }
 
+    fn add_random_cell_with(&mut self, position: Vec2, radius: Scalar) -> CellId {
         let object_id = self.physics.add_object(position, radius);
 
+        let mut cell = Cell::random(&mut self.rng, object_id, radius);
+        if self.brain_type == BrainType::Neat {
+            cell.neurons =
+                Neurons::random_neat(&mut self.rng, &mut self.neurons_mutation.innovations);
+        }
+        self.insert_cell(cell, &[])
+    }
+
+    fn insert_cell(&mut self, mut cell: Cell, parents: &[Lineage]) -> CellId {
         let cell_id = self.next_cell_id;
         self.next_cell_id += 1;
-        let cell = Cell::random(object_id, radius);
+        cell.lineage = Lineage::new(cell_id, parents);
+        self.phylogeny.add(&cell.lineage, self.time);
+        self.speciation
+            .on_cell_born(cell_id, parents.first().map(|parent| parent.cell_id));
+        self.object_cell.insert(cell.object_id, cell_id);
         self.cells.insert(cell_id, cell);
-        self.object_cell.insert(object_id, cell_id);
         cell_id
     }
 
-    fn find_free_position(&mut self, rng: &mut ThreadRng, radius: Scalar) -> Vec2 {
+    fn find_free_position(&mut self, radius: Scalar) -> Vec2 {
         loop {
-            let x = rng.gen_range(0.0..self.world_size.x);
-            let y = rng.gen_range(0.0..self.world_size.y);
+            let x = self.rng.gen_range(0.0..self.world_size.x);
+            let y = self.rng.gen_range(0.0..self.world_size.y);
             let position = Vec2::new(x, y);
             let no_collision = self
                 .get_cell_id_closer_to(x, y)
                 .and_then(|cell_id| self.cells.get(&cell_id))
                 .and_then(|cell| self.physics.get_object(cell.object_id))
-                .map_or(true, |object| {
+                .is_none_or(|object| {
                     (position - object.position()).norm() > radius + object.radius()
                 });
 
@@ -108,10 +387,48 @@ impl Simulator {
         }
     }
 
-    pub fn cells(&self) -> Cells {
+    pub fn cells(&self) -> Cells<'_> {
         Cells(self.cells.iter())
     }
 
+    /// Replaces the neuronal network of the cell with the controller, until it dies or is removed.
+    /// Returns false if there is no such cell.
+    pub fn set_controller(
+        &mut self,
+        cell_id: CellId,
+        controller: impl Controller + 'static,
+    ) -> bool {
+        if !self.cells.contains_key(&cell_id) {
+            return false;
+        }
+        self.controllers.insert(cell_id, Box::new(controller));
+        true
+    }
+
+    /// Removes the controller of the cell, so it uses its own neuronal network again.
+    pub fn remove_controller(&mut self, cell_id: CellId) -> Option<Box<dyn Controller>> {
+        self.controllers.remove(&cell_id)
+    }
+
+    pub fn has_controller(&self, cell_id: CellId) -> bool {
+        self.controllers.contains_key(&cell_id)
+    }
+
+    /// Moves the cell to the position, stopping it. Returns false if there is no such cell.
+    pub fn move_cell(&mut self, cell_id: CellId, position: Vec2) -> bool {
+        self.cells
+            .get(&cell_id)
+            .and_then(|cell| self.physics.get_object_mut(cell.object_id))
+            .map(|object| object.set_position(position))
+            .is_some()
+    }
+
+    /// Removes a living cell, without inserting it into the rank.
+    /// Returns false if there is no such cell.
+    pub fn remove_cell(&mut self, cell_id: CellId) -> bool {
+        self.take_cell(cell_id, DeathCause::Removed).is_some()
+    }
+
     pub fn get_cell_object(&self, id: CellId) -> Option<&Object> {
         self.cells
             .get(&id)
@@ -158,17 +475,26 @@ impl Simulator {
     }
 
     pub fn update(&mut self, dt: Scalar) {
+        self.time += dt;
+        self.rank.update(self.time);
         self.dead_cells.clear();
         self.physics.update(dt);
         self.handle_contacts(dt);
         self.update_cells(dt);
+        self.mate_cells();
         self.remove_dead_cells();
         self.add_born_cells();
+        self.speciation.update(
+            self.time,
+            self.cells.iter().map(|(cell_id, cell)| (*cell_id, cell)),
+        );
+        self.update_statistics();
     }
 
     fn handle_contacts(&mut self, dt: Scalar) {
         for (_, cell) in self.cells.iter_mut() {
             cell.contact_count = 0.0;
+            cell.contact_energy_delta = 0.0;
         }
         for contact in self.physics.contacts() {
             // println!(">>>");
@@ -189,6 +515,10 @@ impl Simulator {
                         .object_cell
                         .get(id2)
                         .and_then(|cell_id| self.cells.get(cell_id).map(|cell| (cell_id, cell)));
+                    let mating = cell1.zip(cell2).and_then(|((id1, cell1), (id2, cell2))| {
+                        (cell1.should_mate() && cell2.should_mate()).then_some((*id1, *id2))
+                    });
+                    self.mating_cells.extend(mating);
                     let energy_deltas = cell1.zip(cell2).map(|((id1, cell1), (id2, cell2))| {
                         let delta1 = cell1.energy_absorption_from(cell2, dt);
                         // println!("delta1: {:.4}", delta1);
@@ -211,14 +541,48 @@ impl Simulator {
         }
     }
 
+    /// Every cell senses and prepares the inputs of its neuronal network first,
+    /// then the networks are evaluated, one by one or together in batches,
+    /// and finally every cell acts with the outputs.
     fn update_cells(&mut self, dt: Scalar) {
-        for (id, cell) in self.cells.iter_mut() {
-            if let Some(object) = self.physics.get_object_mut(cell.object_id) {
+        let mut energy_deltas = Vec::with_capacity(self.cells.len());
+        for cell in self.cells.values_mut() {
+            energy_deltas.push(self.physics.get_object(cell.object_id).map(|object| {
+                let context = SimulationContext {
+                    object,
+                    plasticity: self.plasticity,
+                };
+                cell.sense(dt, &context)
+            }));
+        }
+
+        let mut neurons = Vec::with_capacity(self.cells.len());
+        for ((id, cell), energy_delta) in self.cells.iter_mut().zip(energy_deltas.iter()) {
+            if energy_delta.is_some() {
+                match self.controllers.get_mut(id) {
+                    Some(controller) => cell.neurons.control(controller.as_mut()),
+                    None => neurons.push(&mut cell.neurons),
+                }
+            }
+        }
+        if self.batch_evaluation {
+            Neurons::evaluate_batch(&mut neurons);
+        } else {
+            neurons.iter_mut().for_each(|neurons| neurons.evaluate());
+        }
+
+        for ((id, cell), energy_delta) in self.cells.iter_mut().zip(energy_deltas) {
+            if let Some((object, energy_delta)) = self
+                .physics
+                .get_object_mut(cell.object_id)
+                .zip(energy_delta)
+            {
                 let context = SimulationContext {
                     // reactions: &self.reactions,
                     object,
+                    plasticity: self.plasticity,
                 };
-                cell.update(dt, context);
+                cell.act(dt, energy_delta, context);
                 object.set_radius(cell.contracted_size());
 
                 let current_velocity = object.velocity();
@@ -226,56 +590,197 @@ impl Simulator {
                 // object.set_velocity(cell.movement_velocity, dt);
                 // object.set_acceleration(cell.movement_velocity / (object.mass() * dt));
             }
-            if cell.is_dead() {
-                self.dead_cells.push(*id);
+            if let Some(cause) = cell.death_cause() {
+                self.dead_cells.push((*id, cause));
             } else if cell.should_divide() {
                 let born_cell = cell.divide(&mut self.physics);
-                self.born_cells.push(born_cell);
+                let parent = cell.lineage.clone();
+                self.born_cells.push((Birth::Division(parent), born_cell));
+            }
+        }
+    }
+
+    /// Crosses the pairs of touching cells that signaled to mate.
+    /// The pairs are collected with the contacts, before updating the cells,
+    /// so both parents need to be checked again, as they could have died or divided since.
+    fn mate_cells(&mut self) {
+        let mut mated = HashSet::new();
+        for (id1, id2) in std::mem::take(&mut self.mating_cells) {
+            if mated.contains(&id1) || mated.contains(&id2) {
+                continue;
+            }
+            let offspring = self
+                .cells
+                .get(&id1)
+                .zip(self.cells.get(&id2))
+                .filter(|(cell1, cell2)| {
+                    !cell1.is_dead()
+                        && !cell2.is_dead()
+                        && cell1.should_mate()
+                        && cell2.should_mate()
+                })
+                .and_then(|(cell1, cell2)| {
+                    let object1 = self.physics.get_object(cell1.object_id)?;
+                    let object2 = self.physics.get_object(cell2.object_id)?;
+                    let position = 0.5 * (object1.position() + object2.position());
+                    let genome = cell1.genome().cross(
+                        &mut self.rng,
+                        &cell2.genome(),
+                        self.crossover_operator,
+                    );
+                    let cost = 0.5 * (cell1.mating_cost() + cell2.mating_cost());
+                    let parents = (cell1.lineage.clone(), cell2.lineage.clone());
+                    Some((position, genome, cost, parents))
+                });
+
+            if let Some((position, genome, cost, (parent1, parent2))) = offspring {
+                // The parents share the cost of mating and contribute equally to the offspring
+                let mut energy = 0.0;
+                let mut molecules = V::<NUM_MOLECULES>::zeros();
+                for parent_id in [id1, id2] {
+                    if let Some(parent) = self.cells.get_mut(&parent_id) {
+                        let (parent_energy, parent_molecules) =
+                            parent.mating_contribution(0.5 * cost);
+                        energy += parent_energy;
+                        molecules += parent_molecules;
+                    }
+                }
+                let object_id = self.physics.add_object(position, 1.0);
+                let born_cell =
+                    Cell::offspring_from(&mut self.rng, object_id, &genome, energy, molecules);
+                self.born_cells
+                    .push((Birth::Mating(parent1, parent2), born_cell));
+                mated.insert(id1);
+                mated.insert(id2);
             }
         }
     }
 
     fn remove_dead_cells(&mut self) {
-        for cell_id in self.dead_cells.drain(..) {
-            if let Some(cell) = self.cells.remove(&cell_id) {
-                // TODO transfer any remaining molecules/energy to the world
-                let object_id = cell.object_id;
-                self.physics.remove_object(object_id);
-                let fitness_score = Self::energy_fitness_score(&cell);
-                self.rank.insert(fitness_score, cell);
+        for (cell_id, cause) in std::mem::take(&mut self.dead_cells) {
+            let species_id = self.speciation.species_of(cell_id);
+            if let Some(cell) = self.take_cell(cell_id, cause) {
+                let fitness_score = self.fitness_function.score(&cell);
+                self.rank.insert(
+                    fitness_score,
+                    cell.genome(),
+                    cell.lineage,
+                    cell.age,
+                    species_id,
+                );
             }
         }
     }
 
+    /// Removes the cell from the simulation and its physics.
+    fn take_cell(&mut self, cell_id: CellId, cause: DeathCause) -> Option<Cell> {
+        let cell = self.cells.remove(&cell_id)?;
+        self.add_event(EventKind::Died { cell_id, cause });
+        self.phylogeny.set_died(cell_id, self.time);
+        self.controllers.remove(&cell_id);
+        // TODO transfer any remaining molecules/energy to the world
+        let object_id = cell.object_id;
+        self.physics.remove_object(object_id);
+        self.object_cell.remove(&object_id);
+        self.speciation.on_cell_died(cell_id);
+        Some(cell)
+    }
+
     fn add_born_cells(&mut self) {
-        for born_cell in self.born_cells.drain(..) {
-            self.cells.insert(self.next_cell_id, born_cell);
-            self.next_cell_id += 1;
+        for (birth, mut born_cell) in std::mem::take(&mut self.born_cells) {
+            self.neurons_mutation
+                .apply(&mut self.rng, &mut born_cell.neurons);
+            let kind = match birth {
+                Birth::Division(parent) => {
+                    let child_id = self.insert_cell(born_cell, std::slice::from_ref(&parent));
+                    EventKind::Divided {
+                        cell_id: parent.cell_id,
+                        child_id,
+                    }
+                }
+                Birth::Mating(parent1, parent2) => {
+                    let parent1_id = parent1.cell_id;
+                    let parent2_id = parent2.cell_id;
+                    let cell_id = self.insert_cell(born_cell, &[parent1, parent2]);
+                    EventKind::Born {
+                        cell_id,
+                        parent1_id,
+                        parent2_id,
+                    }
+                }
+            };
+            self.add_event(kind);
         }
 
         while self.cells.len() < self.min_cells {
-            if let Some(genome) = self.create_recombined_genome() {
-                self.add_cell(genome);
+            let elite = self
+                .rank
+                .take_elite()
+                .map(|entry| (entry.genome.clone(), entry.lineage.clone()));
+            let (cell_id, source) = if let Some((genome, parent)) = elite {
+                (self.add_cell(genome, &[parent]), ReseedSource::Elite)
+            } else if let Some((genome, parents)) = self.create_recombined_genome() {
+                let cell_id = self.add_cell(genome, &parents);
+                if let Some(cell) = self.cells.get_mut(&cell_id) {
+                    self.neurons_mutation
+                        .apply(&mut self.rng, &mut cell.neurons);
+                }
+                (cell_id, ReseedSource::Rank)
             } else {
-                self.add_random_cell();
-            }
+                (self.add_random_cell_anywhere(), ReseedSource::Random)
+            };
+            self.add_event(EventKind::Reseeded { cell_id, source });
         }
     }
 
-    fn create_recombined_genome(&self) -> Option<Genome> {
-        let genome1 = self.rank.choose_random_genome();
-        let genome2 = self.rank.choose_random_genome();
-        genome1
-            .zip(genome2)
-            .map(|(genome1, genome2)| genome1.cross(&genome2))
+    fn update_statistics(&mut self) {
+        if self.statistics.on_step() {
+            self.statistics
+                .sample(self.time, self.cells.values(), self.rank.scores());
+        }
     }
 
-    fn energy_fitness_score(cell: &Cell) -> Scalar {
-        let stats = &cell.stats;
-        let energy_positive = stats.energy_produced + stats.energy_absorbed_in;
-        let energy_negative = stats.energy_consumed + stats.energy_absorbed_out;
-        (1.0 + energy_positive) / (1.0 + energy_negative)
+    fn add_event(&mut self, kind: EventKind) {
+        let event = Event {
+            time: self.time,
+            kind,
+        };
+        self.statistics.on_event(&event);
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
     }
+
+    fn create_recombined_genome(&mut self) -> Option<(Genome, [Lineage; 2])> {
+        let entry1 = self.rank.choose_random_entry(&mut self.rng);
+        // The second parent is preferred from the same species than the first one
+        let entry2 = entry1.and_then(|entry1| {
+            entry1
+                .species_id
+                .and_then(|species_id| {
+                    self.rank
+                        .choose_random_entry_matching(&mut self.rng, |entry| {
+                            entry.species_id == Some(species_id)
+                                && entry.lineage.cell_id != entry1.lineage.cell_id
+                        })
+                })
+                .or_else(|| self.rank.choose_random_entry(&mut self.rng))
+        });
+        entry1.zip(entry2).map(|(entry1, entry2)| {
+            let genome =
+                entry1
+                    .genome
+                    .cross(&mut self.rng, &entry2.genome, self.crossover_operator);
+            (genome, [entry1.lineage.clone(), entry2.lineage.clone()])
+        })
+    }
+}
+
+/// How a new cell was born.
+enum Birth {
+    Division(Lineage),
+    Mating(Lineage, Lineage),
 }
 
 pub struct Cells<'a>(Iter<'a, CellId, Cell>);
@@ -331,4 +836,220 @@ impl<'a> Display for CellView<'a> {
 pub struct SimulationContext<'a> {
     // pub(crate) reactions: &'a M<NUM_MOLECULES, NUM_MOLECULES>,
     pub(crate) object: &'a Object,
+    pub(crate) plasticity: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_kept_until_drained() {
+        let mut simulator = Simulator::new(Vec2::new(200.0, 200.0))
+            .with_min_cells(5)
+            .with_seed(1);
+        simulator.update(0.01);
+        simulator.update(0.01);
+        let reseeds = simulator
+            .events()
+            .filter(|event| matches!(event.kind, EventKind::Reseeded { .. }))
+            .count();
+        assert_eq!(reseeds, 5);
+
+        assert!(simulator.drain_events().count() >= 5);
+        assert_eq!(simulator.events().count(), 0);
+    }
+
+    #[test]
+    fn simulators_with_the_same_seed_are_reproducible() {
+        let build = || {
+            Simulator::new(Vec2::new(200.0, 200.0))
+                .with_min_cells(10)
+                .with_seed(7)
+        };
+        let state = |simulator: &Simulator| -> Vec<_> {
+            simulator
+                .cells()
+                .map(|(cell_id, cell)| {
+                    let genes: Vec<_> = cell.genome().genes().map(|(_, value)| value).collect();
+                    (cell_id, cell.energy, genes)
+                })
+                .collect()
+        };
+        let mut simulator1 = build();
+        let mut simulator2 = build();
+        // They are updated alternately, so they would interfere if they shared the generator
+        for _ in 0..50 {
+            simulator1.update(0.01);
+            simulator2.update(0.01);
+        }
+
+        assert_eq!(state(&simulator1), state(&simulator2));
+    }
+
+    #[test]
+    fn added_and_removed_cells_are_reported() {
+        let mut simulator = Simulator::new(Vec2::new(200.0, 200.0)).with_seed(1);
+        let cell_id = simulator.add_random_cell_at(Vec2::new(100.0, 100.0));
+        let genome = simulator.get_cell_view(cell_id).unwrap().genome();
+        let clone_id =
+            simulator.add_cell_with_genome(&genome, Vec2::new(150.0, 100.0), Some(cell_id));
+        assert!(simulator.remove_cell(cell_id));
+        simulator.update(0.01);
+
+        let events: Vec<_> = simulator.drain_events().map(|event| event.kind).collect();
+        assert!(matches!(
+            events[0],
+            EventKind::Added {
+                parent_id: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            events[1],
+            EventKind::Added { cell_id: added_id, parent_id: Some(parent_id) }
+                if added_id == clone_id && parent_id == cell_id
+        ));
+        assert!(matches!(
+            events[2],
+            EventKind::Died {
+                cause: DeathCause::Removed,
+                ..
+            }
+        ));
+    }
+
+    /// Makes the cell ready to mate, as long as it signals it.
+    fn prepare_to_mate(cell: &mut Cell, mate: Scalar) {
+        cell.neurons.set_output("mate", &[mate]);
+        cell.energy = 2.0 * cell.mating_cost();
+        cell.division_energy_reserve = cell.division_threshold;
+        cell.division_grow_factor = 1.0;
+    }
+
+    #[test]
+    fn cells_mate_only_when_they_are_ready() {
+        let mut simulator = Simulator::new(Vec2::new(200.0, 200.0)).with_seed(1);
+        let cell_id = simulator.add_random_cell_at(Vec2::new(100.0, 100.0));
+        let cell = simulator.cells.get_mut(&cell_id).unwrap();
+
+        prepare_to_mate(cell, 1.0);
+        assert!(cell.should_mate());
+        cell.neurons.set_output("mate", &[-1.0]);
+        assert!(!cell.should_mate());
+
+        prepare_to_mate(cell, 1.0);
+        cell.energy = 0.5 * cell.mating_cost();
+        assert!(!cell.should_mate());
+
+        prepare_to_mate(cell, 1.0);
+        cell.division_energy_reserve = 0.25 * cell.division_threshold;
+        assert!(!cell.should_mate());
+
+        prepare_to_mate(cell, 1.0);
+        cell.division_grow_factor = 0.5;
+        assert!(!cell.should_mate());
+    }
+
+    #[test]
+    fn mating_conserves_energy_and_molecules() {
+        let mut simulator = Simulator::new(Vec2::new(200.0, 200.0)).with_seed(1);
+        let id1 = simulator.add_random_cell_at(Vec2::new(100.0, 100.0));
+        let id2 = simulator.add_random_cell_at(Vec2::new(150.0, 100.0));
+        let totals = |simulator: &Simulator| {
+            let mut energy = 0.0;
+            let mut molecules = V::<NUM_MOLECULES>::zeros();
+            for cell in simulator.cells.values() {
+                energy += cell.energy + cell.division_energy_reserve;
+                molecules += cell.molecules;
+            }
+            for (_, cell) in simulator.born_cells.iter() {
+                energy += cell.energy + cell.division_energy_reserve;
+                molecules += cell.molecules;
+            }
+            (energy, molecules)
+        };
+
+        // Both parents need to want to mate
+        prepare_to_mate(simulator.cells.get_mut(&id1).unwrap(), 1.0);
+        prepare_to_mate(simulator.cells.get_mut(&id2).unwrap(), -1.0);
+        let (energy, molecules) = totals(&simulator);
+        simulator.mating_cells.push((id1, id2));
+        simulator.mate_cells();
+        assert!(simulator.born_cells.is_empty());
+        assert_eq!(totals(&simulator), (energy, molecules));
+
+        prepare_to_mate(simulator.cells.get_mut(&id2).unwrap(), 1.0);
+        let cost =
+            0.5 * (simulator.cells[&id1].mating_cost() + simulator.cells[&id2].mating_cost());
+        let reserves = simulator.cells[&id1].division_energy_reserve
+            + simulator.cells[&id2].division_energy_reserve;
+        let (energy, molecules) = totals(&simulator);
+        // Mating twice in the same step is ignored
+        simulator.mating_cells.extend([(id1, id2), (id2, id1)]);
+        simulator.mate_cells();
+
+        assert_eq!(simulator.born_cells.len(), 1);
+        let (birth, child) = &simulator.born_cells[0];
+        assert!(matches!(birth, Birth::Mating(..)));
+        assert_eq!(child.energy, reserves);
+        for cell_id in [id1, id2] {
+            assert_eq!(simulator.cells[&cell_id].division_energy_reserve, 0.0);
+        }
+        let (energy_after, molecules_after) = totals(&simulator);
+        assert!((energy - cost - energy_after).abs() < 1e-9);
+        assert!((molecules - molecules_after).amax() < 1e-9);
+        assert!(child.molecules.iter().all(|amount| *amount >= 0.0));
+    }
+
+    /// Signals to mate, and nothing else.
+    struct AlwaysMate;
+
+    impl Controller for AlwaysMate {
+        fn control(&mut self, neurons: &mut Neurons) {
+            neurons.set_output("mate", &[1.0]);
+        }
+    }
+
+    #[test]
+    fn controllers_replace_the_outputs_of_their_cells() {
+        let mut simulator = Simulator::new(Vec2::new(200.0, 200.0)).with_seed(1);
+        let closure_id = simulator.add_random_cell_at(Vec2::new(50.0, 100.0));
+        let struct_id = simulator.add_random_cell_at(Vec2::new(100.0, 100.0));
+        let brain_id = simulator.add_random_cell_at(Vec2::new(150.0, 100.0));
+        let ages = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let closure_ages = std::rc::Rc::clone(&ages);
+        assert!(
+            simulator.set_controller(closure_id, move |neurons: &mut Neurons| {
+                closure_ages
+                    .borrow_mut()
+                    .push(neurons.input("age").unwrap()[0]);
+                neurons.set_output("movement_kinetic_speed", &[0.5]);
+            })
+        );
+        assert!(simulator.set_controller(struct_id, AlwaysMate));
+        assert!(!simulator.set_controller(100, AlwaysMate));
+
+        simulator.update(0.01);
+        simulator.update(0.01);
+        assert_eq!(ages.borrow().len(), 2);
+        assert!(ages.borrow()[1] > ages.borrow()[0]);
+        let outputs = |simulator: &Simulator, cell_id| {
+            let neurons = simulator.cells[&cell_id].neurons();
+            (
+                neurons.get_movement_kinetic_speed(),
+                neurons.get_mate(),
+                neurons.get_contraction_amount(),
+            )
+        };
+        assert_eq!(outputs(&simulator, closure_id), (0.5, 0.0, 0.0));
+        assert_eq!(outputs(&simulator, struct_id), (0.0, 1.0, 0.0));
+        assert_ne!(outputs(&simulator, brain_id), (0.0, 0.0, 0.0));
+
+        // Without the controller, the network decides again
+        assert!(simulator.remove_controller(struct_id).is_some());
+        assert!(!simulator.has_controller(struct_id));
+        simulator.update(0.01);
+        assert_ne!(outputs(&simulator, struct_id), (0.0, 1.0, 0.0));
+    }
 }
@@ -0,0 +1,343 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::cell::Cell;
+use crate::genome::Genome;
+use crate::simulator::CellId;
+use crate::Scalar;
+
+pub type SpeciesId = usize;
+
+/// Random genomes are usually less than 2/3 apart, so they start in the same species,
+/// which splits as the lineages diverge.
+pub const DEFAULT_SPECIATION_THRESHOLD: Scalar = 0.8;
+pub const DEFAULT_SPECIATION_INTERVAL: usize = 1800;
+
+/// Group of cells with compatible genomes.
+#[derive(Debug, Clone)]
+pub struct Species {
+    pub id: SpeciesId,
+    /// Genome used to decide whether other cells belong to the species.
+    /// It is the genome of the founder, and then of the oldest member after every clustering.
+    pub representative: Genome,
+    /// Number of living cells.
+    pub size: usize,
+    /// Simulation time when the species appeared.
+    pub founded: Scalar,
+    /// Genes of the representative, to compare them with the cells.
+    representative_genes: GeneVector,
+}
+
+/// Values of the genes of a genome, sorted by key.
+/// The keys are shared by all the genomes with the same genes, like all the layered networks,
+/// so the distance between them is computed without comparing the keys.
+#[derive(Debug, Clone)]
+struct GeneVector {
+    keys: Rc<[String]>,
+    values: Vec<Scalar>,
+}
+
+impl GeneVector {
+    /// Same as [`Genome::distance`] between the genomes of both vectors.
+    fn distance(&self, other: &GeneVector) -> Scalar {
+        if Rc::ptr_eq(&self.keys, &other.keys) {
+            let difference = self
+                .values
+                .iter()
+                .zip(other.values.iter())
+                .map(|(value1, value2)| Genome::gene_difference(*value1, *value2))
+                .sum();
+            Genome::combine_distance(0, self.values.len(), difference)
+        } else {
+            Genome::sorted_distance(self.genes(), other.genes())
+        }
+    }
+
+    fn genes(&self) -> impl Iterator<Item = (&str, Scalar)> + '_ {
+        self.keys
+            .iter()
+            .map(String::as_str)
+            .zip(self.values.iter().copied())
+    }
+}
+
+/// Clusters the living cells into species, NEAT-style.
+/// At the end of every interval, the cells are assigned to the first species whose representative is
+/// closer than the threshold, and new species are founded for the cells that don't fit in any.
+/// In between, newborn cells are assigned to the species of their first parent.
+/// Then the representative of every species is replaced by the genome of its oldest member,
+/// so the species follow the evolution of their cells.
+pub struct Speciation {
+    threshold: Scalar,
+    interval: usize,
+    steps: usize,
+    next_species_id: SpeciesId,
+    species: BTreeMap<SpeciesId, Species>,
+    cell_species: HashMap<CellId, SpeciesId>,
+    /// Genes of the living cells, built the first time they are clustered.
+    genes: HashMap<CellId, GeneVector>,
+    /// Keys of the gene vectors, shared by the ones with the same genes.
+    layouts: HashSet<Rc<[String]>>,
+}
+
+impl Speciation {
+    pub fn new(threshold: Scalar, interval: usize) -> Self {
+        Self {
+            threshold,
+            interval: interval.max(1),
+            steps: 0,
+            next_species_id: 0,
+            species: BTreeMap::new(),
+            cell_species: HashMap::new(),
+            genes: HashMap::new(),
+            layouts: HashSet::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> Scalar {
+        self.threshold
+    }
+
+    pub fn species(&self) -> impl Iterator<Item = &Species> + '_ {
+        self.species.values()
+    }
+
+    pub fn get(&self, species_id: SpeciesId) -> Option<&Species> {
+        self.species.get(&species_id)
+    }
+
+    pub fn species_of(&self, cell_id: CellId) -> Option<SpeciesId> {
+        self.cell_species.get(&cell_id).copied()
+    }
+
+    pub(crate) fn on_cell_born(&mut self, cell_id: CellId, parent_id: Option<CellId>) {
+        let species_id = parent_id.and_then(|parent_id| self.species_of(parent_id));
+        if let Some(species) = species_id.and_then(|id| self.species.get_mut(&id)) {
+            species.size += 1;
+            self.cell_species.insert(cell_id, species.id);
+        }
+    }
+
+    pub(crate) fn on_cell_died(&mut self, cell_id: CellId) {
+        self.genes.remove(&cell_id);
+        let species_id = self.cell_species.remove(&cell_id);
+        if let Some(species) = species_id.and_then(|id| self.species.get_mut(&id)) {
+            species.size = species.size.saturating_sub(1);
+        }
+    }
+
+    /// Forgets the genes of the cell, so they are built again the next time the cells are clustered.
+    pub(crate) fn on_genome_changed(&mut self, cell_id: CellId) {
+        self.genes.remove(&cell_id);
+    }
+
+    /// Clusters the cells again when it is due, at the end of every interval.
+    /// In between, the genes of the cells are built a few at a time,
+    /// so the clustering doesn't stall the simulation.
+    pub(crate) fn update<'a>(
+        &mut self,
+        time: Scalar,
+        cells: impl Iterator<Item = (CellId, &'a Cell)>,
+    ) {
+        let remaining_steps = self.interval - self.steps % self.interval;
+        self.steps += 1;
+        if remaining_steps > 1 {
+            let missing: Vec<_> = cells
+                .filter(|(cell_id, _)| !self.genes.contains_key(cell_id))
+                .collect();
+            let amount = missing.len().div_ceil(remaining_steps - 1);
+            for (cell_id, cell) in missing.into_iter().take(amount) {
+                let cell_genes = self.gene_vector(&cell.genome());
+                self.genes.insert(cell_id, cell_genes);
+            }
+            return;
+        }
+
+        for species in self.species.values_mut() {
+            species.size = 0;
+        }
+
+        let mut genes = std::mem::take(&mut self.genes);
+        let mut cell_species = HashMap::new();
+        // Cell ids grow with time, so the oldest member has the lowest one
+        let mut oldest_members = HashMap::<SpeciesId, (CellId, &Cell)>::new();
+        for (cell_id, cell) in cells {
+            let cell_genes = genes
+                .entry(cell_id)
+                .or_insert_with(|| self.gene_vector(&cell.genome()));
+            // Cells try to stay in their current species first
+            let current = self
+                .species_of(cell_id)
+                .and_then(|species_id| self.species.get(&species_id))
+                .filter(|species| {
+                    species.representative_genes.distance(cell_genes) <= self.threshold
+                });
+            let compatible = current.or_else(|| {
+                self.species.values().find(|species| {
+                    species.representative_genes.distance(cell_genes) <= self.threshold
+                })
+            });
+            let species_id = match compatible.map(|species| species.id) {
+                Some(species_id) => species_id,
+                None => self.found_species(cell.genome(), cell_genes.clone(), time),
+            };
+            if let Some(species) = self.species.get_mut(&species_id) {
+                species.size += 1;
+            }
+            cell_species.insert(cell_id, species_id);
+            oldest_members
+                .entry(species_id)
+                .and_modify(|oldest| {
+                    if cell_id < oldest.0 {
+                        *oldest = (cell_id, cell);
+                    }
+                })
+                .or_insert((cell_id, cell));
+        }
+
+        self.species.retain(|_, species| species.size > 0);
+        for (species_id, (cell_id, cell)) in oldest_members {
+            if let Some((species, cell_genes)) =
+                self.species.get_mut(&species_id).zip(genes.get(&cell_id))
+            {
+                species.representative = cell.genome();
+                species.representative_genes = cell_genes.clone();
+            }
+        }
+        genes.retain(|cell_id, _| cell_species.contains_key(cell_id));
+        self.genes = genes;
+        self.cell_species = cell_species;
+        self.layouts.retain(|keys| Rc::strong_count(keys) > 1);
+    }
+
+    /// Builds the gene vector, sharing the keys with the previous ones with the same genes.
+    fn gene_vector(&mut self, genome: &Genome) -> GeneVector {
+        let keys: Vec<_> = genome.genes().map(|(key, _)| key.to_string()).collect();
+        let keys = match self.layouts.get(keys.as_slice()) {
+            Some(keys) => Rc::clone(keys),
+            None => {
+                let keys: Rc<[String]> = keys.into();
+                self.layouts.insert(Rc::clone(&keys));
+                keys
+            }
+        };
+        GeneVector {
+            keys,
+            values: genome.genes().map(|(_, value)| value).collect(),
+        }
+    }
+
+    fn found_species(
+        &mut self,
+        representative: Genome,
+        representative_genes: GeneVector,
+        time: Scalar,
+    ) -> SpeciesId {
+        let id = self.next_species_id;
+        self.next_species_id += 1;
+        let species = Species {
+            id,
+            representative,
+            size: 0,
+            founded: time,
+            representative_genes,
+        };
+        self.species.insert(id, species);
+        id
+    }
+}
+impl Default for Speciation {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPECIATION_THRESHOLD, DEFAULT_SPECIATION_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genome::{Gen, GenomeBuilder};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn random_cells(count: usize) -> Vec<(CellId, Cell)> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..count)
+            .map(|cell_id| (cell_id, Cell::random(&mut rng, cell_id, 5.0)))
+            .collect()
+    }
+
+    fn cluster(speciation: &mut Speciation, cells: &[(CellId, Cell)]) {
+        speciation.update(0.0, cells.iter().map(|(cell_id, cell)| (*cell_id, cell)));
+    }
+
+    #[test]
+    fn gene_vectors_have_the_same_distance_as_genomes() {
+        let cells = random_cells(2);
+        let genome1 = cells[0].1.genome();
+        let genome2 = cells[1].1.genome();
+        let smaller = GenomeBuilder::new();
+        smaller.add("size", Gen::new(3.0));
+        let smaller = smaller.build();
+
+        let mut speciation = Speciation::default();
+        let genes1 = speciation.gene_vector(&genome1);
+        let genes2 = speciation.gene_vector(&genome2);
+        let smaller_genes = speciation.gene_vector(&smaller);
+        assert!(Rc::ptr_eq(&genes1.keys, &genes2.keys));
+        assert_eq!(genes1.distance(&genes2), genome1.distance(&genome2));
+        assert_eq!(genes1.distance(&smaller_genes), genome1.distance(&smaller));
+    }
+
+    #[test]
+    fn random_cells_share_a_species_by_default() {
+        let cells = random_cells(20);
+        let mut speciation = Speciation::new(DEFAULT_SPECIATION_THRESHOLD, 1);
+        cluster(&mut speciation, &cells);
+
+        assert_eq!(speciation.species().count(), 1);
+        let species = speciation.species().next().unwrap();
+        assert_eq!(species.size, 20);
+        assert_eq!(speciation.species_of(0), Some(species.id));
+    }
+
+    #[test]
+    fn cells_farther_than_the_threshold_found_new_species() {
+        let mut cells = random_cells(3);
+        let mut rng = StdRng::seed_from_u64(1);
+        let clone = Cell::from_genome(&mut rng, 3, &cells[0].1.genome());
+        cells.push((3, clone));
+        let mut speciation = Speciation::new(0.01, 1);
+        cluster(&mut speciation, &cells);
+
+        assert_eq!(speciation.species().count(), 3);
+        assert_eq!(speciation.species_of(0), speciation.species_of(3));
+        assert_ne!(speciation.species_of(0), speciation.species_of(1));
+    }
+
+    #[test]
+    fn cells_are_clustered_every_interval_and_newborns_join_their_parent() {
+        let cells = random_cells(2);
+        let mut speciation = Speciation::new(0.01, 3);
+        // The genes are built before the end of the interval
+        cluster(&mut speciation, &cells);
+        assert_eq!(speciation.genes.len(), 1);
+        assert_eq!(speciation.species().count(), 0);
+        cluster(&mut speciation, &cells);
+        assert_eq!(speciation.genes.len(), 2);
+        cluster(&mut speciation, &cells);
+        assert_eq!(speciation.species().count(), 2);
+        let species_id = speciation.species_of(0);
+
+        speciation.on_cell_born(5, Some(0));
+        assert_eq!(speciation.species_of(5), species_id);
+        assert_eq!(speciation.get(species_id.unwrap()).unwrap().size, 2);
+
+        // Not due yet, so the newborn keeps the species of its parent
+        cluster(&mut speciation, &cells[..1]);
+        cluster(&mut speciation, &cells[..1]);
+        assert_eq!(speciation.species_of(5), species_id);
+        cluster(&mut speciation, &cells[..1]);
+        assert_eq!(speciation.species_of(5), None);
+        assert_eq!(speciation.species().count(), 1);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::cell::Cell;
+use crate::events::{Event, EventKind};
+use crate::Scalar;
+
+pub const DEFAULT_SAMPLE_INTERVAL: usize = 60;
+
+/// Maximum number of samples kept. The oldest ones are discarded first.
+pub const MAX_SAMPLES: usize = 36_000;
+
+/// Summary of the distribution of a population variable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Distribution {
+    pub mean: Scalar,
+    pub min: Scalar,
+    pub p10: Scalar,
+    pub p25: Scalar,
+    pub median: Scalar,
+    pub p75: Scalar,
+    pub p90: Scalar,
+    pub max: Scalar,
+}
+
+impl Distribution {
+    const CSV_COLUMNS: [&str; 8] = ["mean", "min", "p10", "p25", "median", "p75", "p90", "max"];
+
+    pub fn from_values(mut values: Vec<Scalar>) -> Self {
+        values.retain(|value| !value.is_nan());
+        if values.is_empty() {
+            return Self::default();
+        }
+        values.sort_by(Scalar::total_cmp);
+        let mean = values.iter().sum::<Scalar>() / values.len() as Scalar;
+        Self {
+            mean,
+            min: values[0],
+            p10: Self::percentile(&values, 0.10),
+            p25: Self::percentile(&values, 0.25),
+            median: Self::percentile(&values, 0.5),
+            p75: Self::percentile(&values, 0.75),
+            p90: Self::percentile(&values, 0.90),
+            max: values[values.len() - 1],
+        }
+    }
+
+    /// Linear interpolation between the closest ranks of sorted values.
+    fn percentile(sorted_values: &[Scalar], fraction: Scalar) -> Scalar {
+        let rank = fraction * (sorted_values.len() - 1) as Scalar;
+        let lower = rank.floor() as usize;
+        let upper = rank.ceil() as usize;
+        let weight = rank - lower as Scalar;
+        sorted_values[lower] * (1.0 - weight) + sorted_values[upper] * weight
+    }
+
+    fn values(&self) -> [Scalar; 8] {
+        [
+            self.mean,
+            self.min,
+            self.p10,
+            self.p25,
+            self.median,
+            self.p75,
+            self.p90,
+            self.max,
+        ]
+    }
+}
+
+/// Population statistics at a given simulation time.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub time: Scalar,
+    pub population: usize,
+    /// Energy available, stored in molecules and reserved for division by all the cells.
+    pub total_energy: Scalar,
+    /// Cells born by division or mating, or added from outside the simulation, since the previous sample.
+    pub births: usize,
+    /// Cells dead since the previous sample.
+    pub deaths: usize,
+    /// Cells added to keep the minimum population since the previous sample.
+    pub reseeds: usize,
+    pub energy: Distribution,
+    pub age: Distribution,
+    pub size: Distribution,
+    pub movement_speed_limit: Distribution,
+    /// Fitness scores of the cells in the rank.
+    pub fitness: Distribution,
+}
+
+/// Collects time-series samples of the population every few simulation steps.
+pub struct Statistics {
+    sample_interval: usize,
+    steps: usize,
+    births: usize,
+    deaths: usize,
+    reseeds: usize,
+    samples: VecDeque<Sample>,
+}
+
+impl Statistics {
+    pub fn new(sample_interval: usize) -> Self {
+        Self {
+            sample_interval: sample_interval.max(1),
+            steps: 0,
+            births: 0,
+            deaths: 0,
+            reseeds: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn sample_interval(&self) -> usize {
+        self.sample_interval
+    }
+
+    /// Samples sorted by time. Only the last [`MAX_SAMPLES`] are kept.
+    pub fn samples(&self) -> &VecDeque<Sample> {
+        &self.samples
+    }
+
+    pub fn last_sample(&self) -> Option<&Sample> {
+        self.samples.back()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Accounts for an event, even if it happened between simulation steps.
+    pub(crate) fn on_event(&mut self, event: &Event) {
+        match event.kind {
+            EventKind::Born { .. } | EventKind::Divided { .. } | EventKind::Added { .. } => {
+                self.births += 1
+            }
+            EventKind::Died { .. } => self.deaths += 1,
+            EventKind::Reseeded { .. } => self.reseeds += 1,
+        }
+    }
+
+    /// Counts a simulation step, and returns whether a sample is due.
+    pub(crate) fn on_step(&mut self) -> bool {
+        self.steps += 1;
+        self.steps.is_multiple_of(self.sample_interval)
+    }
+
+    pub(crate) fn sample<'a>(
+        &mut self,
+        time: Scalar,
+        cells: impl Iterator<Item = &'a Cell>,
+        fitness_scores: impl Iterator<Item = Scalar>,
+    ) {
+        let mut population = 0;
+        let mut total_energy = 0.0;
+        let mut energy = Vec::new();
+        let mut age = Vec::new();
+        let mut size = Vec::new();
+        let mut movement_speed_limit = Vec::new();
+        for cell in cells {
+            population += 1;
+            total_energy += cell.energy + cell.stored_energy + cell.division_energy_reserve;
+            energy.push(cell.energy);
+            age.push(cell.age);
+            size.push(cell.size);
+            movement_speed_limit.push(cell.movement_speed_limit);
+        }
+
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            time,
+            population,
+            total_energy,
+            births: std::mem::take(&mut self.births),
+            deaths: std::mem::take(&mut self.deaths),
+            reseeds: std::mem::take(&mut self.reseeds),
+            energy: Distribution::from_values(energy),
+            age: Distribution::from_values(age),
+            size: Distribution::from_values(size),
+            movement_speed_limit: Distribution::from_values(movement_speed_limit),
+            fitness: Distribution::from_values(fitness_scores.collect()),
+        });
+    }
+
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut header = vec![
+            "time".to_string(),
+            "population".to_string(),
+            "total_energy".to_string(),
+            "births".to_string(),
+            "deaths".to_string(),
+            "reseeds".to_string(),
+        ];
+        for name in ["energy", "age", "size", "movement_speed_limit", "fitness"] {
+            header.extend(
+                Distribution::CSV_COLUMNS
+                    .iter()
+                    .map(|column| format!("{name}_{column}")),
+            );
+        }
+        writeln!(writer, "{}", header.join(","))?;
+
+        for sample in self.samples.iter() {
+            let mut row = vec![
+                sample.time.to_string(),
+                sample.population.to_string(),
+                sample.total_energy.to_string(),
+                sample.births.to_string(),
+                sample.deaths.to_string(),
+                sample.reseeds.to_string(),
+            ];
+            for distribution in [
+                &sample.energy,
+                &sample.age,
+                &sample.size,
+                &sample.movement_speed_limit,
+                &sample.fitness,
+            ] {
+                row.extend(distribution.values().iter().map(Scalar::to_string));
+            }
+            writeln!(writer, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_interpolate_between_ranks() {
+        let values = [1.0, 2.0, 4.0, 8.0, 16.0];
+        assert_eq!(Distribution::percentile(&values, 0.0), 1.0);
+        assert_eq!(Distribution::percentile(&values, 0.5), 4.0);
+        assert_eq!(Distribution::percentile(&values, 0.625), 6.0);
+        assert_eq!(Distribution::percentile(&values, 1.0), 16.0);
+        assert_eq!(Distribution::percentile(&[3.0], 0.9), 3.0);
+
+        let distribution = Distribution::from_values(vec![4.0, Scalar::NAN, 0.0, 2.0]);
+        assert_eq!(distribution.mean, 2.0);
+        assert_eq!(distribution.min, 0.0);
+        assert_eq!(distribution.p25, 1.0);
+        assert_eq!(distribution.median, 2.0);
+        assert_eq!(distribution.max, 4.0);
+    }
+
+    #[test]
+    fn samples_are_written_as_csv() {
+        let mut statistics = Statistics::new(1);
+        statistics.sample(0.5, std::iter::empty(), [10.0, 0.0].into_iter());
+        statistics.sample(1.5, std::iter::empty(), std::iter::empty());
+        let mut csv = Vec::new();
+        statistics.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[0].starts_with("time,population,total_energy,births,deaths,reseeds,energy_mean,")
+        );
+        assert!(lines[0].ends_with(",fitness_p90,fitness_max"));
+        let header_columns = lines[0].split(',').count();
+        assert_eq!(header_columns, 6 + 5 * 8);
+        assert_eq!(lines[1].split(',').count(), header_columns);
+        assert!(lines[1].starts_with("0.5,0,0,0,0,0,"));
+        let fitness: Vec<_> = lines[1].split(',').skip(6 + 4 * 8).collect();
+        assert_eq!(fitness, ["5", "0", "1", "2.5", "5", "7.5", "9", "10"]);
+        assert!(lines[2].starts_with("1.5,0,"));
+    }
+}